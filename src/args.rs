@@ -1,22 +1,74 @@
+use crate::index::DuplicatePolicy;
+
+/// The output format of the rebuild plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Package names separated by whitespace
+    Text,
+    /// The full rebuild plan as JSON
+    Json,
+}
+
 #[derive(Debug, clap::Parser)]
 #[clap(name = "arch-rebuild-order", about, author)]
 pub struct Args {
     /// List of input packages
-    #[arg(required = true)]
+    #[arg(required_unless_present_any = ["clear_cache", "all"])]
     pub pkgnames: Vec<String>,
 
-    /// Repositories
-    #[arg(
-        default_value = "core,extra,multilib",
-        long,
-        use_value_delimiter = true
-    )]
-    pub repos: Vec<String>,
+    /// Read the repositories, the root directory, the pacman database path and the architecture
+    /// from the given pacman.conf, the options below override it
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Repositories, default ( core,extra,multilib )
+    #[arg(long, use_value_delimiter = true)]
+    pub repos: Option<Vec<String>>,
+
+    /// Layer the testing repositories over the repositories, such as core-testing over core
+    #[arg(long)]
+    pub testing: bool,
+
+    /// Layer the staging and testing repositories over the repositories, such as core-staging
+    /// over core-testing over core
+    #[arg(long)]
+    pub staging: bool,
 
-    /// The path to the pacman database, default ( /var/lib/pacman )
+    /// How to choose between packages of the same name in several repositories
+    #[arg(long, value_enum, default_value_t = DuplicatePolicy::RepoOrder)]
+    pub duplicates: DuplicatePolicy,
+
+    /// The root directory, default ( / )
+    #[arg(long)]
+    pub root: Option<String>,
+
+    /// The path to the pacman database, default ( /var/lib/pacman ) in the root directory
     #[arg(long)]
     pub dbpath: Option<String>,
 
+    /// Read a repository database file instead of the pacman database, such as
+    /// `extra=/path/extra.db`, repeat for every repository in repository order
+    #[arg(long, value_name = "REPO=PATH", value_parser = parse_db_file)]
+    pub db_file: Vec<(String, String)>,
+
+    /// Overlay the packages of the .SRCINFO files in the given directory, one directory per
+    /// pkgbase, on top of the repositories
+    #[arg(long, value_name = "DIR")]
+    pub srcinfo: Option<String>,
+
+    /// Only use the packages of the .SRCINFO files given with --srcinfo
+    #[arg(long, requires = "srcinfo")]
+    pub srcinfo_only: bool,
+
+    /// The architecture to read architecture specific .SRCINFO keys for, default ( x86_64 )
+    #[arg(long)]
+    pub arch: Option<String>,
+
+    /// Overlay the packages built in the given directory (*.pkg.tar.zst) on top of the
+    /// repositories, replacing the packages of the same name
+    #[arg(long, value_name = "DIR")]
+    pub built_packages: Option<String>,
+
     /// Write a dotfile into the given file
     #[arg(short, long)]
     pub dotfile: Option<String>,
@@ -25,7 +77,80 @@ pub struct Args {
     #[arg(long)]
     pub no_reverse_depends: bool,
 
+    /// Order the dependencies (depends and makedepends) of the input packages instead of their
+    /// reverse dependencies, such as for bootstrapping a repository
+    #[arg(long, conflicts_with_all = ["no_reverse_depends", "soname"])]
+    pub depends: bool,
+
+    /// Order every package of the repositories instead of the input packages, such as for a mass
+    /// rebuild
+    #[arg(
+        long,
+        conflicts_with_all = ["pkgnames", "no_reverse_depends", "soname", "depends", "why"]
+    )]
+    pub all: bool,
+
     /// Include checkdepends
     #[arg(long)]
     pub with_check_depends: bool,
+
+    /// Group the rebuild order into stages of packages which can be built in parallel
+    #[arg(long)]
+    pub stages: bool,
+
+    /// Merge split packages and output one entry per pkgbase
+    #[arg(long)]
+    pub pkgbase: bool,
+
+    /// Only rebuild reverse dependencies linking against a soname provided by the input packages
+    #[arg(long)]
+    pub soname: bool,
+
+    /// Bump a package to a new version, following versioned dependencies satisfied by it and
+    /// warning about those it breaks, such as `foo=2.0-1`
+    #[arg(long, value_name = "PKGNAME=VERSION", value_parser = parse_bump)]
+    pub bump: Vec<(String, String)>,
+
+    /// Print the shortest dependency chain from the input packages to the given package instead
+    /// of the rebuild order
+    #[arg(long, value_name = "PKGNAME")]
+    pub why: Option<String>,
+
+    /// Print every dependency chain with --why
+    #[arg(long, requires = "why")]
+    pub all_chains: bool,
+
+    /// Do not load or write the cached dependency index
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Remove the cached dependency indexes
+    #[arg(long)]
+    pub clear_cache: bool,
+
+    /// The output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+/// Parse a `PKGNAME=VERSION` package bump.
+fn parse_bump(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((pkgname, version)) if !pkgname.is_empty() && !version.is_empty() => {
+            Ok((pkgname.to_string(), version.to_string()))
+        }
+        _ => Err(format!("invalid bump '{value}', expected PKGNAME=VERSION")),
+    }
+}
+
+/// Parse a `REPO=PATH` repository database file.
+fn parse_db_file(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((repo, path)) if !repo.is_empty() && !path.is_empty() => {
+            Ok((repo.to_string(), path.to_string()))
+        }
+        _ => Err(format!(
+            "invalid database file '{value}', expected REPO=PATH"
+        )),
+    }
 }
@@ -0,0 +1,117 @@
+use crate::index::{DependencyIndex, DuplicatePolicy};
+use anyhow::Result;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// The version of the cache format, cache files of other versions are ignored and removed.
+const CACHE_VERSION: u32 = 5;
+
+/// Hash the bytes with 64-bit FNV-1a, which unlike `DefaultHasher` is the same on every Rust
+/// release.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Return the cache directory in `$XDG_CACHE_HOME`, falling back to `~/.cache`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    Some(base.join("arch-rebuild-order"))
+}
+
+/// Return the cache file of the index of the given root directory, pacman database, repositories
+/// and policy, named by the cache version and a hash of the key.
+fn cache_file(
+    cache_dir: &Path,
+    root: Option<&str>,
+    dbpath: Option<&str>,
+    repos: &[String],
+    policy: DuplicatePolicy,
+) -> PathBuf {
+    let key = serde_json::to_vec(&(root, dbpath, repos, policy)).unwrap_or_default();
+
+    cache_dir.join(format!("index-v{CACHE_VERSION}-{:016x}.json", fnv1a(&key)))
+}
+
+/// Load the index from the cache file, if it indexes the given root directory, pacman database
+/// and repositories with the given policy and none of their syncdbs changed.
+fn load(
+    path: &Path,
+    root: Option<&str>,
+    dbpath: Option<&str>,
+    repos: &[String],
+    policy: DuplicatePolicy,
+) -> Option<DependencyIndex> {
+    let file = File::open(path).ok()?;
+    let index: DependencyIndex = serde_json::from_reader(BufReader::new(file)).ok()?;
+
+    (index.indexes(root, dbpath, repos, policy) && !index.is_stale()).then_some(index)
+}
+
+/// Remove the cache files of other cache versions from the cache directory.
+fn prune(cache_dir: &Path) -> Result<()> {
+    let current = format!("index-v{CACHE_VERSION}-");
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with("index-") && !name.starts_with(&current) {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the index to the cache file, replacing it at once so readers never see a partial file.
+fn store(path: &Path, index: &DependencyIndex) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp = path.with_extension("json.tmp");
+    let mut bufw = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut bufw, index)?;
+    bufw.into_inner().map_err(|e| e.into_error())?;
+    fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+/// Load the index of the given root directory, pacman database and repositories with the given
+/// policy from the cache directory when their syncdbs are unchanged, otherwise build the index
+/// and write it to the cache, removing the cache files of other cache versions.
+///
+/// Failing to write the cache is not an error, the index is built again next time.
+pub fn load_or_build(
+    cache_dir: &Path,
+    root: Option<&str>,
+    dbpath: Option<&str>,
+    repos: &[String],
+    policy: DuplicatePolicy,
+) -> Result<DependencyIndex> {
+    let path = cache_file(cache_dir, root, dbpath, repos, policy);
+    if let Some(index) = load(&path, root, dbpath, repos, policy) {
+        return Ok(index);
+    }
+
+    let index = DependencyIndex::with_root(root, dbpath, repos, policy)?;
+    let _ = store(&path, &index);
+    let _ = prune(cache_dir);
+
+    Ok(index)
+}
+
+/// Remove all cached indexes from the cache directory.
+pub fn clear(cache_dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(cache_dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
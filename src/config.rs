@@ -0,0 +1,87 @@
+//! Parser for the settings of pacman.conf used to find the repositories.
+
+use crate::error::RebuildOrderError;
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+/// The maximum depth of nested `Include` directives, as in pacman.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// The settings of a pacman.conf.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacmanConf {
+    /// The `RootDir` option
+    pub root_dir: Option<String>,
+    /// The `DBPath` option
+    pub db_path: Option<String>,
+    /// The first `Architecture`, `auto` being the architecture of this machine
+    pub architecture: Option<String>,
+    /// The repositories in order
+    pub repos: Vec<String>,
+}
+
+impl PacmanConf {
+    /// Parse the pacman.conf at the given path, following `Include` directives.
+    pub fn parse_file(path: &Path) -> Result<PacmanConf> {
+        let mut conf = PacmanConf::default();
+        conf.parse(path, &mut String::new(), 0)?;
+
+        Ok(conf)
+    }
+
+    fn parse(&mut self, path: &Path, section: &mut String, depth: usize) -> Result<()> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| RebuildOrderError::read("pacman.conf", path, e))?;
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                *section = name.to_string();
+                if name != "options" && !self.repos.iter().any(|x| x == name) {
+                    self.repos.push(name.to_string());
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match (section.as_str(), key.trim()) {
+                (_, "Include") => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(RebuildOrderError::read(
+                            "pacman.conf",
+                            path,
+                            "too many nested Include directives",
+                        )
+                        .into());
+                    }
+                    let paths = glob::glob(value)
+                        .map_err(|e| RebuildOrderError::read("pacman.conf", path, e))?;
+                    for include in paths {
+                        let include =
+                            include.map_err(|e| RebuildOrderError::read("pacman.conf", path, e))?;
+                        self.parse(&include, section, depth + 1)?;
+                    }
+                }
+                ("options", "RootDir") => self.root_dir = Some(value.to_string()),
+                ("options", "DBPath") => self.db_path = Some(value.to_string()),
+                ("options", "Architecture") => {
+                    self.architecture = value.split_whitespace().next().map(|arch| match arch {
+                        "auto" => std::env::consts::ARCH.to_string(),
+                        _ => arch.to_string(),
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{DependKind, RebuildGraph};
+use petgraph::algo::tarjan_scc;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+
+/// A dependency cycle in the rebuild graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyCycle {
+    /// The packages forming the cycle, sorted by name
+    pub packages: Vec<String>,
+    /// The dependencies forming the cycle as (package, reverse dependency, kind)
+    pub edges: Vec<(String, String, DependKind)>,
+}
+
+impl fmt::Display for DependencyCycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dependency cycle between {}", self.packages.join(", "))?;
+        for (pkg, rev_dep, kind) in &self.edges {
+            write!(f, "\n  {rev_dep} {kind} on {pkg}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Find all dependency cycles in the rebuild graph.
+///
+/// Every strongly connected component with more than one package, or a package depending on
+/// itself, is a cycle.
+pub fn find_cycles(graph: &RebuildGraph) -> Vec<DependencyCycle> {
+    let mut cycles = Vec::new();
+
+    for scc in cyclic_components(graph) {
+        let members: HashSet<_> = scc.iter().collect();
+        let mut packages: Vec<String> = scc.iter().map(|&nx| graph[nx].clone()).collect();
+        packages.sort();
+
+        let mut edges = Vec::new();
+        for &nx in &scc {
+            for edge in graph.edges(nx) {
+                if members.contains(&edge.target()) {
+                    edges.push((
+                        graph[nx].clone(),
+                        graph[edge.target()].clone(),
+                        edge.weight().kind,
+                    ));
+                }
+            }
+        }
+        edges.sort();
+
+        cycles.push(DependencyCycle { packages, edges });
+    }
+
+    cycles.sort_by(|a, b| a.packages.cmp(&b.packages));
+    cycles
+}
+
+/// Rebuild graph with all dependency cycles broken, which can be ordered.
+#[derive(Debug, Clone)]
+pub struct BuildGraph {
+    /// The acyclic graph, a bootstrap build is a separate node with the same package name
+    pub graph: RebuildGraph,
+    /// Nodes which are a bootstrap build of a package
+    pub bootstrap: HashSet<NodeIndex>,
+    /// Dependencies ignored to break a cycle as (package, reverse dependency, kind)
+    pub dropped: Vec<(String, String, DependKind)>,
+}
+
+/// Return the strongly connected components of the graph which form a cycle.
+fn cyclic_components(graph: &RebuildGraph) -> Vec<Vec<NodeIndex>> {
+    tarjan_scc(graph)
+        .into_iter()
+        .filter(|scc| scc.len() > 1 || graph.contains_edge(scc[0], scc[0]))
+        .collect()
+}
+
+/// Pick the package of a cycle to bootstrap, preferring the provided packages and then the
+/// package most other packages in the cycle depend on.
+fn bootstrap_candidate(
+    graph: &RebuildGraph,
+    scc: &[NodeIndex],
+    pkgnames: &HashSet<&str>,
+) -> NodeIndex {
+    let members: HashSet<_> = scc.iter().collect();
+    *scc.iter()
+        .max_by_key(|&&nx| {
+            let dependents = graph
+                .edges(nx)
+                .filter(|edge| members.contains(&edge.target()))
+                .count();
+            (
+                pkgnames.contains(graph[nx].as_str()),
+                dependents,
+                Reverse(graph[nx].as_str()),
+            )
+        })
+        .expect("strongly connected components are never empty")
+}
+
+/// Break all dependency cycles of the rebuild graph.
+///
+/// Check dependencies forming a cycle are dropped first, then make dependencies. When a cycle of
+/// runtime dependencies remains one package of the cycle is built twice: a bootstrap build which
+/// the other packages of the cycle are built against, followed by a final rebuild after them.
+pub fn break_cycles(graph: &RebuildGraph, pkgnames: &[String]) -> BuildGraph {
+    let mut graph = graph.clone();
+    let pkgnames: HashSet<&str> = pkgnames.iter().map(|x| x.as_str()).collect();
+    let mut bootstrap = HashSet::new();
+    let mut dropped = Vec::new();
+
+    for kind in [DependKind::CheckDepends, DependKind::MakeDepends] {
+        let mut drop = Vec::new();
+        for scc in cyclic_components(&graph) {
+            let members: HashSet<_> = scc.iter().collect();
+            for &nx in &scc {
+                for edge in graph.edges(nx) {
+                    if edge.weight().kind == kind && members.contains(&edge.target()) {
+                        drop.push(edge.id());
+                    }
+                }
+            }
+        }
+
+        // Removing an edge invalidates the last edge index, so remove from the back.
+        drop.sort();
+        for edge in drop.into_iter().rev() {
+            let (pkg, rev_dep) = graph
+                .edge_endpoints(edge)
+                .expect("edge to drop is part of the graph");
+            dropped.push((graph[pkg].clone(), graph[rev_dep].clone(), kind));
+            graph.remove_edge(edge);
+        }
+    }
+    dropped.sort();
+
+    loop {
+        let sccs = cyclic_components(&graph);
+        if sccs.is_empty() {
+            break;
+        }
+
+        for scc in sccs {
+            let members: HashSet<_> = scc.iter().copied().collect();
+            let pkg = bootstrap_candidate(&graph, &scc, &pkgnames);
+            let bootstrap_node = graph.add_node(graph[pkg].clone());
+            bootstrap.insert(bootstrap_node);
+
+            // The bootstrap build requires the dependencies from outside of the cycle.
+            let outside_deps: Vec<_> = graph
+                .edges_directed(pkg, Direction::Incoming)
+                .filter(|edge| !members.contains(&edge.source()))
+                .map(|edge| (edge.source(), edge.weight().clone()))
+                .collect();
+            for (dep, dependency) in outside_deps {
+                graph.add_edge(dep, bootstrap_node, dependency);
+            }
+
+            // The other packages of the cycle are built against the bootstrap build.
+            let mut cycle_rev_deps: Vec<_> = graph
+                .edges(pkg)
+                .filter(|edge| members.contains(&edge.target()))
+                .map(|edge| (edge.id(), edge.target(), edge.weight().clone()))
+                .collect();
+            cycle_rev_deps.sort_by_key(|(edge, _, _)| Reverse(*edge));
+            for (edge, rev_dep, dependency) in cycle_rev_deps {
+                graph.remove_edge(edge);
+                graph.add_edge(bootstrap_node, rev_dep, dependency);
+            }
+        }
+    }
+
+    BuildGraph {
+        graph,
+        bootstrap,
+        dropped,
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -7,10 +8,30 @@ pub enum RebuildOrderError {
     #[error("package not found")]
     PackageNotFound,
 
+    /// Given package is not part of the rebuild graph
+    #[error("package not in rebuild graph: `{0}`")]
+    NotInRebuildGraph(String),
+
+    /// Given options cannot be used together
+    #[error("option `{0}` cannot be used with `{1}`")]
+    ConflictingOptions(&'static str, &'static str),
+
     /// Pacman database failed to initialize
     #[error("could not initialize pacman db: `{0}`")]
     PacmanDbInit(#[from] alpm::Error),
 
+    /// Reading an input file, such as a repository database or pacman.conf, failed
+    #[error("could not read {kind} `{}`: {source}", path.display())]
+    ReadError {
+        /// What the file was read as
+        kind: &'static str,
+        /// The path of the file
+        path: PathBuf,
+        /// The error reading the file
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
     /// Writing dotfile failed
     #[error("could not write to file: `{0}`")]
     DotfileError(#[from] io::Error),
@@ -19,3 +40,18 @@ pub enum RebuildOrderError {
     #[error("unknown error")]
     Unknown,
 }
+
+impl RebuildOrderError {
+    /// The error reading the file at `path` as `kind`, such as `pacman.conf`.
+    pub(crate) fn read(
+        kind: &'static str,
+        path: &Path,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> RebuildOrderError {
+        RebuildOrderError::ReadError {
+            kind,
+            path: path.to_path_buf(),
+            source: source.into(),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Construction of the rebuild graph from the dependency index, following reverse dependencies
+//! or, when ordering dependencies, dependencies of the provided package(s).
+
+use crate::error::RebuildOrderError;
+use crate::index::{self, DependencyIndex};
+use crate::{AmbiguousProvide, Dependency, Rebuild, RebuildGraph, RebuildOrderOptions};
+use anyhow::Result;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Merge the split packages sharing a pkgbase into a single node named after the pkgbase, the
+/// edges of the split packages are combined.
+pub(crate) fn collapse_pkgbase(
+    graph: &RebuildGraph,
+    pkgbases: &HashMap<&str, &str>,
+) -> RebuildGraph {
+    let mut collapsed = RebuildGraph::new();
+    let mut cache_node: HashMap<&str, NodeIndex> = HashMap::new();
+
+    for nx in graph.node_indices() {
+        let pkgbase = pkgbases[graph[nx].as_str()];
+        cache_node
+            .entry(pkgbase)
+            .or_insert_with(|| collapsed.add_node(pkgbase.to_string()));
+    }
+
+    for edge in graph.edge_references() {
+        let pkg = cache_node[pkgbases[graph[edge.source()].as_str()]];
+        let rev_dep = cache_node[pkgbases[graph[edge.target()].as_str()]];
+
+        // Split packages are built together, they do not depend on each other.
+        if pkg == rev_dep {
+            continue;
+        }
+
+        match collapsed.find_edge(pkg, rev_dep) {
+            Some(existing) if collapsed[existing] <= *edge.weight() => {}
+            Some(existing) => collapsed[existing] = edge.weight().clone(),
+            None => {
+                collapsed.add_edge(pkg, rev_dep, edge.weight().clone());
+            }
+        }
+    }
+
+    collapsed
+}
+
+/// Whether a provided name is a soname, such as `libfoo.so`.
+fn is_soname(name: &str) -> bool {
+    name.ends_with(".so")
+}
+
+impl DependencyIndex {
+    /// Build the rebuild graph of provided package(s) from the index, ignoring the `dbpath` and
+    /// `repos` options.
+    pub fn rebuild_graph(&self, options: &RebuildOrderOptions) -> Result<Rebuild> {
+        let RebuildOrderOptions {
+            pkgnames,
+            with_check_depends,
+            pkgbase,
+            bumps,
+            ..
+        } = options;
+        let (with_check_depends, pkgbase) = (*with_check_depends, *pkgbase);
+
+        // Ordering dependencies has no reverse dependencies to restrict to soname users, to follow
+        // through bumped versions or to leave out, and whole repositories have no provided
+        // packages.
+        let mode = match (options.all, options.depends) {
+            (true, _) => Some("all"),
+            (false, true) => Some("depends"),
+            (false, false) => None,
+        };
+        if let Some(mode) = mode {
+            let conflicts = [
+                ("soname", options.soname),
+                ("bumps", !bumps.is_empty()),
+                ("no_reverse_depends", options.no_reverse_depends),
+                ("pkgnames", options.all && !pkgnames.is_empty()),
+            ];
+            if let Some((option, _)) = conflicts.into_iter().find(|(_, set)| *set) {
+                return Err(RebuildOrderError::ConflictingOptions(mode, option).into());
+            }
+        }
+
+        // Every package of the repositories is provided when ordering whole repositories.
+        let all_pkgnames: Vec<String>;
+        let pkgnames = if options.all {
+            all_pkgnames = self.pkgnames();
+            &all_pkgnames
+        } else {
+            pkgnames
+        };
+
+        for pkg in pkgnames.iter().chain(bumps.iter().map(|(pkg, _)| pkg)) {
+            if self.package(pkg).is_none() {
+                return Err(RebuildOrderError::PackageNotFound.into());
+            }
+        }
+
+        let bumps: HashMap<&str, &str> = bumps
+            .iter()
+            .map(|(pkg, version)| (pkg.as_str(), version.as_str()))
+            .collect();
+        let broken_constraints = self.broken_constraints(&bumps, with_check_depends);
+
+        let (graph, ambiguous_provides) = if options.depends || options.all {
+            self.depends_graph(pkgnames, with_check_depends)
+        } else {
+            self.reverse_depends_graph(options, &bumps)
+        };
+
+        let mut ambiguous_provides: Vec<AmbiguousProvide> = ambiguous_provides
+            .into_iter()
+            .map(|(name, providers)| {
+                let mut providers = providers.to_vec();
+                providers.sort();
+                AmbiguousProvide {
+                    name: name.to_string(),
+                    providers,
+                }
+            })
+            .collect();
+        ambiguous_provides.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let (graph, roots) = if pkgbase {
+            let pkgbases: HashMap<&str, &str> = graph
+                .node_weights()
+                .map(|x| x.as_str())
+                .chain(pkgnames.iter().map(|x| x.as_str()))
+                .map(|pkg| (pkg, self.pkgbase(pkg)))
+                .collect();
+
+            let mut roots: Vec<String> = Vec::new();
+            let mut seen = HashSet::new();
+            for pkg in pkgnames {
+                let base = pkgbases[pkg.as_str()];
+                if seen.insert(base) {
+                    roots.push(base.to_string());
+                }
+            }
+
+            (collapse_pkgbase(&graph, &pkgbases), roots)
+        } else {
+            (graph, pkgnames.to_vec())
+        };
+
+        let packages = graph
+            .node_weights()
+            .map(|name| {
+                let pkgname = match self.split_packages_of(name).first() {
+                    Some(split_package) if pkgbase => split_package.as_str(),
+                    _ => name.as_str(),
+                };
+                let info = self
+                    .package(pkgname)
+                    .expect("graph nodes are packages of the index");
+                (name.clone(), info.clone())
+            })
+            .collect();
+
+        Ok(Rebuild {
+            graph,
+            pkgnames: roots,
+            ambiguous_provides,
+            broken_constraints,
+            packages,
+        })
+    }
+
+    /// Build the graph of the reverse dependencies of the provided package(s), returning it with
+    /// the provided names with multiple providers followed.
+    fn reverse_depends_graph<'a>(
+        &'a self,
+        options: &'a RebuildOrderOptions,
+        bumps: &HashMap<&'a str, &'a str>,
+    ) -> (RebuildGraph, HashMap<&'a str, &'a [String]>) {
+        let RebuildOrderOptions {
+            pkgnames,
+            with_check_depends,
+            soname,
+            ..
+        } = options;
+        let (with_check_depends, soname) = (*with_check_depends, *soname);
+
+        // Packages linking against a soname of the provided package(s) are the only rebuild
+        // candidates when rebuilding for a soname bump.
+        let candidates: Option<HashSet<&str>> = soname.then(|| {
+            pkgnames
+                .iter()
+                .flat_map(|pkg| self.provides_of(pkg))
+                .filter(|provide| is_soname(&provide.name))
+                .flat_map(|provide| self.runtime_reverse_deps(&provide.name))
+                .collect()
+        });
+
+        let mut graph = RebuildGraph::new();
+
+        let mut to_visit = VecDeque::new();
+        let mut visited = HashSet::new();
+        let mut ambiguous_provides = HashMap::new();
+
+        to_visit.extend(pkgnames.iter().map(|x| x.as_str()));
+
+        let mut cache_node: HashMap<&str, NodeIndex> = HashMap::new();
+
+        while let Some(pkg) = to_visit.pop_front() {
+            if !visited.insert(pkg) {
+                continue;
+            }
+
+            let root = *cache_node
+                .entry(pkg)
+                .or_insert_with(|| graph.add_node(pkg.to_string()));
+            let root_pkgbase = self.pkgbase(pkg);
+
+            // Reverse dependencies depend on the package by name or on any name it provides, for a
+            // soname bump only the sonames of the provided package(s) are relevant. Every name is
+            // paired with the versions it satisfies version constraints with.
+            let version = self.package(pkg).map(|x| x.version.as_str());
+            let bumped = bumps.get(pkg).copied();
+            let names: Vec<(&str, Vec<Option<&str>>)> =
+                if soname && pkgnames.iter().any(|x| x == pkg) {
+                    self.provides_of(pkg)
+                        .iter()
+                        .filter(|provide| is_soname(&provide.name))
+                        .map(|provide| (provide.name.as_str(), vec![provide.version.as_deref()]))
+                        .collect()
+                } else {
+                    let own = std::iter::once(version).chain(bumped.map(Some)).collect();
+                    std::iter::once((pkg, own))
+                        .chain(self.provides_of(pkg).iter().map(|provide| {
+                            (provide.name.as_str(), vec![provide.version.as_deref()])
+                        }))
+                        .collect()
+                };
+
+            let mut rev_deps_for_pkg: HashMap<&str, Dependency> = HashMap::new();
+            for (name, versions) in names {
+                let found = rev_deps_for_pkg.len();
+                self.collect_reverse_deps(
+                    &mut rev_deps_for_pkg,
+                    pkg,
+                    name,
+                    &versions,
+                    with_check_depends,
+                );
+
+                if rev_deps_for_pkg.len() > found {
+                    let providers = self.providers_of(name);
+                    if providers.len() > 1 {
+                        ambiguous_provides.insert(name, providers);
+                    }
+                }
+            }
+
+            if let Some(candidates) = &candidates {
+                rev_deps_for_pkg.retain(|rev_dep, _| candidates.contains(rev_dep));
+            }
+
+            let mut rev_deps_for_pkg_vec = rev_deps_for_pkg.into_iter().collect::<Vec<_>>();
+            rev_deps_for_pkg_vec.sort();
+
+            for (rev_dep, dependency) in rev_deps_for_pkg_vec {
+                let depnode = *cache_node
+                    .entry(rev_dep)
+                    .or_insert_with(|| graph.add_node(rev_dep.to_string()));
+                to_visit.push_back(rev_dep);
+                if self.pkgbase(rev_dep) == root_pkgbase {
+                    continue;
+                }
+                graph.add_edge(root, depnode, dependency);
+            }
+        }
+
+        (graph, ambiguous_provides)
+    }
+
+    /// Build the graph of the dependencies of the provided package(s), every dependency on a
+    /// name resolved to the package of that name or else to the first provider by name. Edges
+    /// point from a dependency to the package depending on it as in the rebuild graph.
+    /// Dependencies not satisfied by any package are left out.
+    fn depends_graph<'a>(
+        &'a self,
+        pkgnames: &'a [String],
+        with_check_depends: bool,
+    ) -> (RebuildGraph, HashMap<&'a str, &'a [String]>) {
+        let depends_map = self.depends_map(with_check_depends);
+
+        let mut graph = RebuildGraph::new();
+
+        let mut to_visit = VecDeque::new();
+        let mut visited = HashSet::new();
+        let mut ambiguous_provides = HashMap::new();
+
+        to_visit.extend(pkgnames.iter().map(|x| x.as_str()));
+
+        let mut cache_node: HashMap<&str, NodeIndex> = HashMap::new();
+
+        while let Some(pkg) = to_visit.pop_front() {
+            if !visited.insert(pkg) {
+                continue;
+            }
+
+            let root = *cache_node
+                .entry(pkg)
+                .or_insert_with(|| graph.add_node(pkg.to_string()));
+            let root_pkgbase = self.pkgbase(pkg);
+
+            let mut deps_for_pkg: HashMap<&str, Dependency> = HashMap::new();
+            for &(name, entry) in depends_map.get(pkg).into_iter().flatten() {
+                let satisfiers = self.satisfiers(name, entry);
+                let Some(&dep) = satisfiers.first() else {
+                    continue;
+                };
+                if dep != name && satisfiers.len() > 1 {
+                    ambiguous_provides.insert(name, self.providers_of(name));
+                }
+
+                let dependency = Dependency {
+                    kind: entry.kind,
+                    provide: (dep != name).then(|| name.to_string()),
+                };
+                index::insert_dependency(&mut deps_for_pkg, dep, dependency);
+            }
+
+            let mut deps_for_pkg_vec = deps_for_pkg.into_iter().collect::<Vec<_>>();
+            deps_for_pkg_vec.sort();
+
+            for (dep, dependency) in deps_for_pkg_vec {
+                let depnode = *cache_node
+                    .entry(dep)
+                    .or_insert_with(|| graph.add_node(dep.to_string()));
+                to_visit.push_back(dep);
+                if self.pkgbase(dep) == root_pkgbase {
+                    continue;
+                }
+                graph.add_edge(depnode, root, dependency);
+            }
+        }
+
+        (graph, ambiguous_provides)
+    }
+}
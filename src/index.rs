@@ -0,0 +1,629 @@
+use crate::source::{AlpmSource, Depend, PackageSource, SourcePackage};
+use crate::version::Constraint;
+use crate::{db_path, BrokenConstraint, DependKind, Dependency, PackageInfo};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A reverse dependency in the index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ReverseDep {
+    /// The strongest kind of dependency
+    pub(crate) kind: DependKind,
+    /// The version constraints on the name depended on
+    pub(crate) constraints: Vec<Constraint>,
+}
+
+impl ReverseDep {
+    /// Whether all version constraints are satisfied by the given version of the name depended
+    /// on, an unversioned provide never satisfies a versioned dependency.
+    fn satisfied_by(&self, version: Option<&str>) -> bool {
+        self.constraints
+            .iter()
+            .all(|constraint| version.is_some_and(|version| constraint.satisfied_by(version)))
+    }
+}
+
+/// Reverse dependencies of a name mapped to their dependency.
+pub(crate) type ReverseDepsMap = HashMap<String, HashMap<String, ReverseDep>>;
+
+/// Dependencies of a pkgname as the names depended on and their dependency, sorted by name.
+pub(crate) type DependsMap<'a> = HashMap<&'a str, Vec<(&'a str, &'a ReverseDep)>>;
+
+/// A name provided by a package.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Provide {
+    /// The provided name
+    pub(crate) name: String,
+    /// The provided version, such as `1-64` for `libfoo.so=1-64`
+    pub(crate) version: Option<String>,
+}
+
+/// How to choose between packages of the same name in several repositories.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+pub enum DuplicatePolicy {
+    /// The package of the first repository containing it
+    #[default]
+    RepoOrder,
+    /// The package with the highest version, compared using pacman's vercmp, or of the first
+    /// repository on equal versions
+    HighestVersion,
+}
+
+/// A package left out of the index for a package of the same name in another repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShadowedPackage {
+    /// The package name
+    pub pkgname: String,
+    /// The repository of the shadowed package
+    pub repo: String,
+    /// The version of the shadowed package
+    pub version: String,
+    /// The repository of the package used instead
+    pub by_repo: String,
+    /// The version of the package used instead
+    pub by_version: String,
+}
+
+impl fmt::Display for ShadowedPackage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} in {} is shadowed by {} in {}",
+            self.pkgname, self.version, self.repo, self.by_version, self.by_repo
+        )
+    }
+}
+
+/// Index of the reverse dependencies of all packages in the syncdbs.
+///
+/// The index is built once and answers any number of queries without reading the syncdbs again,
+/// [`DependencyIndex::refresh`] rebuilds it when the syncdbs changed. An index can also be built
+/// from any [`PackageSource`], which is never stale. Runtime dependencies belong to a pkgname
+/// while build time dependencies (makedepends and checkdepends) belong to the pkgbase, which is
+/// shared by all of its split packages.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DependencyIndex {
+    /// The syncdbs the index was built from
+    syncdbs: Option<SyncDbs>,
+    /// The repositories of the packages in priority order, as first read from the source
+    repos: Vec<String>,
+    /// Packages left out for a package of the same name in another repository
+    shadowed: Vec<ShadowedPackage>,
+    /// Runtime reverse dependencies as pkgnames
+    runtime: ReverseDepsMap,
+    /// Make time reverse dependencies as pkgbases
+    build: ReverseDepsMap,
+    /// Check time reverse dependencies as pkgbases
+    check: ReverseDepsMap,
+    /// Metadata of every package, the package chosen by the duplicate policy
+    packages: HashMap<String, PackageInfo>,
+    /// The split packages of every pkgbase
+    split_packages: HashMap<String, Vec<String>>,
+    /// The names provided by every package
+    provides: HashMap<String, Vec<Provide>>,
+    /// The packages providing a name, including the package with that name
+    providers: HashMap<String, Vec<String>>,
+}
+
+/// Record `pkgname` as reverse dependency of `dep`, keeping the strongest dependency kind and all
+/// version constraints when the package depends on it in multiple ways.
+fn insert_reverse_dep(
+    reverse_deps: &mut ReverseDepsMap,
+    dep: &Depend,
+    pkgname: &str,
+    kind: DependKind,
+) {
+    let reverse_dep = reverse_deps
+        .entry(dep.name.clone())
+        .or_default()
+        .entry(pkgname.to_string())
+        .or_insert(ReverseDep {
+            kind,
+            constraints: Vec::new(),
+        });
+    reverse_dep.kind = reverse_dep.kind.min(kind);
+
+    if let Some(constraint) = &dep.constraint {
+        if !reverse_dep.constraints.contains(constraint) {
+            reverse_dep.constraints.push(constraint.clone());
+        }
+    }
+}
+
+/// Record `pkgname` as provider of `name`.
+fn insert_provider(providers: &mut HashMap<String, Vec<String>>, name: &str, pkgname: &str) {
+    let entry = providers.entry(name.to_string()).or_default();
+    if !entry.iter().any(|x| x == pkgname) {
+        entry.push(pkgname.to_string());
+    }
+}
+
+/// Record `rev_dep` as reverse dependency, keeping the strongest dependency when it depends on the
+/// package in multiple ways.
+pub(crate) fn insert_dependency<'a>(
+    rev_deps: &mut HashMap<&'a str, Dependency>,
+    rev_dep: &'a str,
+    dependency: Dependency,
+) {
+    match rev_deps.entry(rev_dep) {
+        Entry::Occupied(mut entry) => {
+            if dependency < *entry.get() {
+                entry.insert(dependency);
+            }
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(dependency);
+        }
+    }
+}
+
+/// The pacman database and repositories an index was built from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SyncDbs {
+    /// The root directory
+    root: Option<String>,
+    /// The path to the pacman database
+    dbpath: Option<String>,
+    /// The indexed repositories
+    repos: Vec<String>,
+    /// The syncdb of every repository when indexed
+    stamps: Vec<SyncDbStamp>,
+    /// The policy choosing between packages of the same name
+    policy: DuplicatePolicy,
+}
+
+/// The path, size and modification time of a syncdb, a changed syncdb has a different stamp.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SyncDbStamp {
+    path: PathBuf,
+    size: Option<u64>,
+    mtime: Option<SystemTime>,
+}
+
+/// Retrieve the stamp of the syncdb of every repository.
+fn syncdb_stamps(dbpath: &str, repos: &[String]) -> Vec<SyncDbStamp> {
+    repos
+        .iter()
+        .map(|repo| {
+            let path = Path::new(dbpath).join("sync").join(format!("{repo}.db"));
+            let metadata = path.metadata().ok();
+            SyncDbStamp {
+                size: metadata.as_ref().map(|x| x.len()),
+                mtime: metadata.and_then(|x| x.modified().ok()),
+                path,
+            }
+        })
+        .collect()
+}
+
+impl DependencyIndex {
+    /// Build the index from the syncdbs of the given repositories, in the pacman database at
+    /// `dbpath` or the default ( /var/lib/pacman ).
+    pub fn new(dbpath: Option<&str>, repos: &[String]) -> Result<DependencyIndex> {
+        DependencyIndex::with_root(None, dbpath, repos, DuplicatePolicy::RepoOrder)
+    }
+
+    /// Build the index from the syncdbs of the given repositories, in the root directory `root`
+    /// or the default ( / ) and the pacman database at `dbpath` or `var/lib/pacman` in the root
+    /// directory, choosing between packages of the same name by `policy`.
+    pub fn with_root(
+        root: Option<&str>,
+        dbpath: Option<&str>,
+        repos: &[String],
+        policy: DuplicatePolicy,
+    ) -> Result<DependencyIndex> {
+        let stamps = syncdb_stamps(&db_path(root, dbpath), repos);
+        let source = AlpmSource::new(root, dbpath, repos)?;
+
+        let mut index = DependencyIndex::from_source_with_policy(&source, policy);
+        index.syncdbs = Some(SyncDbs {
+            root: root.map(|x| x.to_string()),
+            dbpath: dbpath.map(|x| x.to_string()),
+            repos: repos.to_vec(),
+            stamps,
+            policy,
+        });
+
+        Ok(index)
+    }
+
+    /// Build the index from the packages of the given source. A package shadows the packages of
+    /// the same name in later repositories, which are left out of the index.
+    pub fn from_source(source: &dyn PackageSource) -> DependencyIndex {
+        DependencyIndex::from_source_with_policy(source, DuplicatePolicy::RepoOrder)
+    }
+
+    /// Build the index from the packages of the given source, choosing between packages of the
+    /// same name by `policy`. The packages not chosen are left out of the index and reported by
+    /// [`DependencyIndex::shadowed`].
+    pub fn from_source_with_policy(
+        source: &dyn PackageSource,
+        policy: DuplicatePolicy,
+    ) -> DependencyIndex {
+        let packages = source.packages();
+
+        let mut chosen: HashMap<&str, usize> = HashMap::new();
+        for (i, pkg) in packages.iter().enumerate() {
+            match chosen.entry(&pkg.name) {
+                Entry::Vacant(entry) => {
+                    entry.insert(i);
+                }
+                Entry::Occupied(mut entry) => {
+                    let version = packages[*entry.get()].version.as_str();
+                    if policy == DuplicatePolicy::HighestVersion
+                        && alpm::vercmp(pkg.version.as_str(), version) == Ordering::Greater
+                    {
+                        entry.insert(i);
+                    }
+                }
+            }
+        }
+
+        let mut index = DependencyIndex::default();
+        for (i, pkg) in packages.iter().enumerate() {
+            if !index.repos.contains(&pkg.repo) {
+                index.repos.push(pkg.repo.clone());
+            }
+
+            let winner = chosen[pkg.name.as_str()];
+            if winner == i {
+                index.insert_package(pkg);
+            } else {
+                index.shadowed.push(ShadowedPackage {
+                    pkgname: pkg.name.clone(),
+                    repo: pkg.repo.clone(),
+                    version: pkg.version.clone(),
+                    by_repo: packages[winner].repo.clone(),
+                    by_version: packages[winner].version.clone(),
+                });
+            }
+        }
+        index
+    }
+
+    /// Record a package and its dependencies in the index.
+    fn insert_package(&mut self, pkg: &SourcePackage) {
+        let pkgname = pkg.name.as_str();
+        let pkgbase = pkg.base.as_deref().unwrap_or(pkgname);
+        self.split_packages
+            .entry(pkgbase.to_string())
+            .or_default()
+            .push(pkgname.to_string());
+
+        self.packages.insert(
+            pkgname.to_string(),
+            PackageInfo {
+                pkgbase: pkgbase.to_string(),
+                repo: pkg.repo.clone(),
+                version: pkg.version.clone(),
+                arch: pkg.arch.clone(),
+            },
+        );
+
+        insert_provider(&mut self.providers, pkgname, pkgname);
+        for provide in &pkg.provides {
+            self.provides
+                .entry(pkgname.to_string())
+                .or_default()
+                .push(Provide {
+                    name: provide.name.clone(),
+                    version: provide.provided_version().map(|x| x.to_string()),
+                });
+            insert_provider(&mut self.providers, &provide.name, pkgname);
+        }
+
+        for dep in &pkg.depends {
+            insert_reverse_dep(&mut self.runtime, dep, pkgname, DependKind::Depends);
+        }
+
+        for dep in &pkg.makedepends {
+            insert_reverse_dep(&mut self.build, dep, pkgbase, DependKind::MakeDepends);
+        }
+
+        for dep in &pkg.checkdepends {
+            insert_reverse_dep(&mut self.check, dep, pkgbase, DependKind::CheckDepends);
+        }
+    }
+
+    /// Whether the index was built from the given root directory, pacman database and
+    /// repositories with the given policy.
+    pub(crate) fn indexes(
+        &self,
+        root: Option<&str>,
+        dbpath: Option<&str>,
+        repos: &[String],
+        policy: DuplicatePolicy,
+    ) -> bool {
+        self.syncdbs.as_ref().is_some_and(|x| {
+            x.root.as_deref() == root
+                && x.dbpath.as_deref() == dbpath
+                && x.repos == repos
+                && x.policy == policy
+        })
+    }
+
+    /// Whether a syncdb was modified since the index was built.
+    pub fn is_stale(&self) -> bool {
+        self.syncdbs.as_ref().is_some_and(|x| {
+            let dbpath = db_path(x.root.as_deref(), x.dbpath.as_deref());
+            syncdb_stamps(&dbpath, &x.repos) != x.stamps
+        })
+    }
+
+    /// Rebuild the index from the syncdbs it was built from.
+    pub fn refresh(&mut self) -> Result<()> {
+        if let Some(syncdbs) = &self.syncdbs {
+            *self = DependencyIndex::with_root(
+                syncdbs.root.as_deref(),
+                syncdbs.dbpath.as_deref(),
+                &syncdbs.repos,
+                syncdbs.policy,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the index when a syncdb was modified since it was built, returning whether it was
+    /// rebuilt.
+    pub fn refresh_if_stale(&mut self) -> Result<bool> {
+        if !self.is_stale() {
+            return Ok(false);
+        }
+        self.refresh()?;
+        Ok(true)
+    }
+
+    /// Retrieve the metadata of a package.
+    pub fn package(&self, pkgname: &str) -> Option<&PackageInfo> {
+        self.packages.get(pkgname)
+    }
+
+    /// Return the repositories of the packages in the index, in priority order.
+    pub fn repos(&self) -> &[String] {
+        &self.repos
+    }
+
+    /// Return the names of all packages in the index, sorted by name.
+    pub fn pkgnames(&self) -> Vec<String> {
+        let mut pkgnames: Vec<String> = self.packages.keys().cloned().collect();
+        pkgnames.sort();
+        pkgnames
+    }
+
+    /// Return the pkgbase of the given package, falling back to its name.
+    pub(crate) fn pkgbase<'a>(&'a self, pkgname: &'a str) -> &'a str {
+        self.packages
+            .get(pkgname)
+            .map(|x| x.pkgbase.as_str())
+            .unwrap_or(pkgname)
+    }
+
+    /// Return the packages left out for a package of the same name in another repository, in
+    /// repository order.
+    pub fn shadowed(&self) -> &[ShadowedPackage] {
+        &self.shadowed
+    }
+
+    /// Return the packages providing a name, including the package with that name.
+    pub(crate) fn providers_of(&self, name: &str) -> &[String] {
+        self.providers
+            .get(name)
+            .map(|x| x.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Return the split packages of a pkgbase.
+    pub(crate) fn split_packages_of(&self, pkgbase: &str) -> &[String] {
+        self.split_packages
+            .get(pkgbase)
+            .map(|x| x.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Return the packages with a runtime dependency on `name`.
+    pub(crate) fn runtime_reverse_deps(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.runtime
+            .get(name)
+            .into_iter()
+            .flat_map(|rev_deps| rev_deps.keys().map(|x| x.as_str()))
+    }
+
+    /// Return the names provided by the given package.
+    pub(crate) fn provides_of(&self, pkgname: &str) -> &[Provide] {
+        self.provides
+            .get(pkgname)
+            .map(|x| x.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Collect the reverse dependencies depending on `name` of package `pkgname`, with version
+    /// constraints satisfied by any of the given versions of the name.
+    ///
+    /// Split packages of the same pkgbase are built together and never depend on each other,
+    /// build time dependencies of a pkgbase apply to all of its split packages.
+    pub(crate) fn collect_reverse_deps<'a>(
+        &'a self,
+        rev_deps: &mut HashMap<&'a str, Dependency>,
+        pkgname: &str,
+        name: &str,
+        versions: &[Option<&str>],
+        with_check_depends: bool,
+    ) {
+        let provide = (name != pkgname).then(|| name.to_string());
+        let satisfied = |rev_dep: &ReverseDep| versions.iter().any(|x| rev_dep.satisfied_by(*x));
+
+        if let Some(runtime) = self.runtime.get(name) {
+            for (rev_dep, entry) in runtime {
+                if !satisfied(entry) {
+                    continue;
+                }
+                let dependency = Dependency {
+                    kind: entry.kind,
+                    provide: provide.clone(),
+                };
+                insert_dependency(rev_deps, rev_dep, dependency);
+            }
+        }
+
+        let pkgbase = self.pkgbase(pkgname);
+        let build = std::iter::once(&self.build).chain(with_check_depends.then_some(&self.check));
+        for map in build {
+            for (rev_dep_pkgbase, entry) in map.get(name).into_iter().flatten() {
+                if rev_dep_pkgbase == pkgbase || !satisfied(entry) {
+                    continue;
+                }
+                for rev_dep in &self.split_packages[rev_dep_pkgbase] {
+                    let dependency = Dependency {
+                        kind: entry.kind,
+                        provide: provide.clone(),
+                    };
+                    insert_dependency(rev_deps, rev_dep, dependency);
+                }
+            }
+        }
+    }
+
+    /// Invert the reverse dependencies into the dependencies of every pkgname, build time
+    /// dependencies of a pkgbase apply to all of its split packages.
+    pub(crate) fn depends_map(&self, with_check_depends: bool) -> DependsMap<'_> {
+        let mut depends: DependsMap = HashMap::new();
+
+        for (name, rev_deps) in &self.runtime {
+            for (rev_dep, entry) in rev_deps {
+                depends.entry(rev_dep).or_default().push((name, entry));
+            }
+        }
+
+        let build = std::iter::once(&self.build).chain(with_check_depends.then_some(&self.check));
+        for map in build {
+            for (name, rev_deps) in map {
+                for (rev_dep_pkgbase, entry) in rev_deps {
+                    for rev_dep in self
+                        .split_packages
+                        .get(rev_dep_pkgbase)
+                        .into_iter()
+                        .flatten()
+                    {
+                        depends.entry(rev_dep).or_default().push((name, entry));
+                    }
+                }
+            }
+        }
+
+        for entries in depends.values_mut() {
+            entries.sort_by(|a, b| (a.0, a.1.kind).cmp(&(b.0, b.1.kind)));
+        }
+        depends
+    }
+
+    /// Find the packages satisfying a dependency on `name`, the package of that name first and
+    /// then the providers of the name sorted by name.
+    pub(crate) fn satisfiers<'a>(&'a self, name: &str, dep: &ReverseDep) -> Vec<&'a str> {
+        let mut satisfiers: Vec<&str> = self
+            .providers
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|pkgname| {
+                if *pkgname == name {
+                    return dep.satisfied_by(self.package(pkgname).map(|x| x.version.as_str()));
+                }
+                self.provides_of(pkgname).iter().any(|provide| {
+                    provide.name == name && dep.satisfied_by(provide.version.as_deref())
+                })
+            })
+            .map(|x| x.as_str())
+            .collect();
+        satisfiers.sort_by_key(|pkgname| (*pkgname != name, *pkgname));
+        satisfiers
+    }
+
+    /// Find the reverse dependencies of a package on its name or any name it provides, sorted by
+    /// name.
+    pub fn reverse_depends(
+        &self,
+        pkgname: &str,
+        with_check_depends: bool,
+    ) -> Vec<(String, Dependency)> {
+        let version = self.package(pkgname).map(|x| x.version.as_str());
+        let mut rev_deps = HashMap::new();
+
+        self.collect_reverse_deps(
+            &mut rev_deps,
+            pkgname,
+            pkgname,
+            &[version],
+            with_check_depends,
+        );
+        for provide in self.provides_of(pkgname) {
+            self.collect_reverse_deps(
+                &mut rev_deps,
+                pkgname,
+                &provide.name,
+                &[provide.version.as_deref()],
+                with_check_depends,
+            );
+        }
+
+        let pkgbase = self.pkgbase(pkgname);
+        let mut rev_deps: Vec<(String, Dependency)> = rev_deps
+            .into_iter()
+            .filter(|(rev_dep, _)| self.pkgbase(rev_dep) != pkgbase)
+            .map(|(rev_dep, dependency)| (rev_dep.to_string(), dependency))
+            .collect();
+        rev_deps.sort();
+        rev_deps
+    }
+
+    /// Find the version constraints on bumped packages which are satisfied by their current version
+    /// but not by their new version.
+    pub(crate) fn broken_constraints(
+        &self,
+        bumps: &HashMap<&str, &str>,
+        with_check_depends: bool,
+    ) -> Vec<BrokenConstraint> {
+        let mut broken = Vec::new();
+
+        for (pkgname, version) in bumps {
+            let current = self.package(pkgname).map(|x| x.version.as_str());
+            let rev_deps = [&self.runtime, &self.build]
+                .into_iter()
+                .chain(with_check_depends.then_some(&self.check))
+                .filter_map(|map| map.get(*pkgname))
+                .flatten();
+
+            for (rev_dep, entry) in rev_deps {
+                for constraint in &entry.constraints {
+                    if current.is_some_and(|x| constraint.satisfied_by(x))
+                        && !constraint.satisfied_by(version)
+                    {
+                        broken.push(BrokenConstraint {
+                            pkgname: pkgname.to_string(),
+                            version: version.to_string(),
+                            rev_dep: rev_dep.to_string(),
+                            kind: entry.kind,
+                            constraint: constraint.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        broken.sort_by_key(|x| {
+            (
+                x.pkgname.clone(),
+                x.rev_dep.clone(),
+                x.constraint.to_string(),
+            )
+        });
+        broken
+    }
+}
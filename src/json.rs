@@ -0,0 +1,294 @@
+//! JSON output of the rebuild plan.
+//!
+//! The schema is versioned by `schema_version`, which is increased for every incompatible change.
+//! Version 1 is an object with:
+//!
+//! * `schema_version`: the schema version, `1`
+//! * `packages`: the packages to rebuild in rebuild order, objects with `pkgname`, `pkgbase`,
+//!   `repo`, `version`, `arch` (or `null`), `depth` from the provided packages, `stage` (numbered
+//!   from 1 as in the stages output), `bootstrap` and `reason`
+//! * `reason`: an object with `type` being `provided`, `reverse_dependency` with the `pkgname`
+//!   depended on, its `kind` and `provide` (or `null`), `dependency` with the `pkgname` depending
+//!   on it, its `kind` and `provide` (or `null`), or `split_package` with the `pkgname` of the
+//!   same pkgbase
+//! * `edges`: the dependencies of the rebuild graph, objects with `from` a package, `to` its
+//!   reverse dependency, the `kind` of dependency (`depends`, `makedepends` or `checkdepends`) and
+//!   the `provide` followed (or `null`)
+//! * `cycles`: the dependency cycles, objects with the `packages` forming it sorted by name and the
+//!   `edges` forming it, objects with `from` a package, `to` its reverse dependency and the `kind`
+//!   of dependency
+//! * `dropped`: the dependencies ignored to break dependency cycles, objects like the `edges` of
+//!   `cycles`
+//! * `ambiguous_provides`: the provided names with multiple providers which were followed, objects
+//!   with the provided `name` and its `providers` sorted by name
+//! * `broken_constraints`: the version constraints broken by packages bumped with `--bump`,
+//!   objects with the bumped `pkgname`, its new `version`, the `rev_dep` (a pkgbase for build time
+//!   dependencies), the `kind` of dependency and the `constraint`, such as `>=2`
+//! * `chains`: the dependency chains asked about with `--why`, objects with the `root` package and
+//!   its `hops`, objects like `edges` from the previous package of the chain to the next, which
+//!   with `--depends` is a package to its dependency instead of its reverse dependency, or with
+//!   the `kind` `split_package` and no `provide` to a split package of the same pkgbase
+//! * `shadowed`: packages of the rebuild graph left out for a package of the same name in another
+//!   repository, objects with `pkgname`, `repo`, `version` and the `by_repo` and `by_version` of
+//!   the package used instead
+
+use crate::plan::{Reason, RebuildPlan};
+use crate::DependKind;
+use serde::Serialize;
+
+/// The version of the JSON schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Plan<'a> {
+    schema_version: u32,
+    packages: Vec<Package<'a>>,
+    edges: Vec<Edge<'a>>,
+    cycles: Vec<Cycle<'a>>,
+    dropped: Vec<CycleEdge<'a>>,
+    ambiguous_provides: Vec<AmbiguousProvide<'a>>,
+    broken_constraints: Vec<BrokenConstraint<'a>>,
+    chains: Vec<Chain<'a>>,
+    shadowed: Vec<Shadowed<'a>>,
+}
+
+#[derive(Serialize)]
+struct Package<'a> {
+    pkgname: &'a str,
+    pkgbase: &'a str,
+    repo: &'a str,
+    version: &'a str,
+    arch: Option<&'a str>,
+    depth: usize,
+    stage: usize,
+    bootstrap: bool,
+    reason: PackageReason<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PackageReason<'a> {
+    Provided,
+    ReverseDependency {
+        pkgname: &'a str,
+        kind: Kind,
+        provide: Option<&'a str>,
+    },
+    Dependency {
+        pkgname: &'a str,
+        kind: Kind,
+        provide: Option<&'a str>,
+    },
+    SplitPackage {
+        pkgname: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Depends,
+    MakeDepends,
+    CheckDepends,
+    #[serde(rename = "split_package")]
+    SplitPackage,
+}
+
+impl From<DependKind> for Kind {
+    fn from(kind: DependKind) -> Self {
+        match kind {
+            DependKind::Depends => Kind::Depends,
+            DependKind::MakeDepends => Kind::MakeDepends,
+            DependKind::CheckDepends => Kind::CheckDepends,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Edge<'a> {
+    from: &'a str,
+    to: &'a str,
+    kind: Kind,
+    provide: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Cycle<'a> {
+    packages: &'a [String],
+    edges: Vec<CycleEdge<'a>>,
+}
+
+#[derive(Serialize)]
+struct CycleEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+    kind: Kind,
+}
+
+#[derive(Serialize)]
+struct AmbiguousProvide<'a> {
+    name: &'a str,
+    providers: &'a [String],
+}
+
+#[derive(Serialize)]
+struct BrokenConstraint<'a> {
+    pkgname: &'a str,
+    version: &'a str,
+    rev_dep: &'a str,
+    kind: Kind,
+    constraint: String,
+}
+
+#[derive(Serialize)]
+struct Shadowed<'a> {
+    pkgname: &'a str,
+    repo: &'a str,
+    version: &'a str,
+    by_repo: &'a str,
+    by_version: &'a str,
+}
+
+#[derive(Serialize)]
+struct Chain<'a> {
+    root: &'a str,
+    hops: Vec<Edge<'a>>,
+}
+
+/// Convert dependencies as package, reverse dependency and kind into JSON edges.
+fn cycle_edges(edges: &[(String, String, DependKind)]) -> Vec<CycleEdge<'_>> {
+    edges
+        .iter()
+        .map(|(from, to, kind)| CycleEdge {
+            from,
+            to,
+            kind: (*kind).into(),
+        })
+        .collect()
+}
+
+/// Serialize the rebuild plan as JSON.
+pub fn to_json(plan: &RebuildPlan) -> serde_json::Result<String> {
+    let packages = plan
+        .entries
+        .iter()
+        .map(|entry| Package {
+            pkgname: &entry.pkgname,
+            pkgbase: &entry.pkgbase,
+            repo: &entry.repo,
+            version: &entry.version,
+            arch: entry.arch.as_deref(),
+            depth: entry.depth,
+            stage: entry.stage + 1,
+            bootstrap: entry.bootstrap,
+            reason: match &entry.reason {
+                Reason::Provided => PackageReason::Provided,
+                Reason::ReverseDependency {
+                    pkgname,
+                    dependency,
+                } => PackageReason::ReverseDependency {
+                    pkgname,
+                    kind: dependency.kind.into(),
+                    provide: dependency.provide.as_deref(),
+                },
+                Reason::Dependency {
+                    pkgname,
+                    dependency,
+                } => PackageReason::Dependency {
+                    pkgname,
+                    kind: dependency.kind.into(),
+                    provide: dependency.provide.as_deref(),
+                },
+                Reason::SplitPackage { pkgname } => PackageReason::SplitPackage { pkgname },
+            },
+        })
+        .collect();
+
+    let graph = &plan.graph;
+    let mut edges: Vec<Edge> = graph
+        .edge_indices()
+        .filter_map(|edge| {
+            let (from, to) = graph.edge_endpoints(edge)?;
+            Some(Edge {
+                from: &graph[from],
+                to: &graph[to],
+                kind: graph[edge].kind.into(),
+                provide: graph[edge].provide.as_deref(),
+            })
+        })
+        .collect();
+    edges.sort_by(|a, b| (a.from, a.to).cmp(&(b.from, b.to)));
+
+    let chains = plan
+        .chains
+        .iter()
+        .map(|chain| {
+            let mut from = chain.root.as_str();
+            let hops = chain
+                .hops
+                .iter()
+                .map(|(rev_dep, dependency)| {
+                    let dependency = dependency.as_ref();
+                    let hop = Edge {
+                        from,
+                        to: rev_dep,
+                        kind: dependency.map_or(Kind::SplitPackage, |x| x.kind.into()),
+                        provide: dependency.and_then(|x| x.provide.as_deref()),
+                    };
+                    from = rev_dep;
+                    hop
+                })
+                .collect();
+            Chain {
+                root: &chain.root,
+                hops,
+            }
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&Plan {
+        schema_version: SCHEMA_VERSION,
+        packages,
+        edges,
+        cycles: plan
+            .cycles
+            .iter()
+            .map(|cycle| Cycle {
+                packages: &cycle.packages,
+                edges: cycle_edges(&cycle.edges),
+            })
+            .collect(),
+        dropped: cycle_edges(&plan.dropped),
+        ambiguous_provides: plan
+            .ambiguous_provides
+            .iter()
+            .map(|x| AmbiguousProvide {
+                name: &x.name,
+                providers: &x.providers,
+            })
+            .collect(),
+        broken_constraints: plan
+            .broken_constraints
+            .iter()
+            .map(|x| BrokenConstraint {
+                pkgname: &x.pkgname,
+                version: &x.version,
+                rev_dep: &x.rev_dep,
+                kind: x.kind.into(),
+                constraint: x.constraint.to_string(),
+            })
+            .collect(),
+        chains,
+        shadowed: plan
+            .shadowed
+            .iter()
+            .map(|x| Shadowed {
+                pkgname: &x.pkgname,
+                repo: &x.repo,
+                version: &x.version,
+                by_repo: &x.by_repo,
+                by_version: &x.by_version,
+            })
+            .collect(),
+    })
+}
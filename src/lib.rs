@@ -1,198 +1,497 @@
-use alpm::{Package, SigLevel};
-use anyhow::{anyhow, Result};
-use error::RebuildOrderError;
-use petgraph::dot::{Config, Dot};
-use petgraph::graph::DiGraph;
-use petgraph::visit::DfsPostOrder;
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use anyhow::Result;
+use config::PacmanConf;
+use index::{DependencyIndex, DuplicatePolicy};
+use petgraph::graph::{DiGraph, NodeIndex};
+use pkginfo::PkginfoSource;
+use plan::RebuildPlan;
+use repodb::RepoDbSource;
+use serde::{Deserialize, Serialize};
+use source::{AlpmSource, MemorySource, OverlaySource, PackageSource};
+use srcinfo::SrcinfoSource;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use version::Constraint;
 
 pub mod args;
+pub mod cache;
+pub mod config;
+pub mod cycles;
 pub mod error;
+pub mod graph;
+pub mod index;
+pub mod json;
+pub mod pkginfo;
+pub mod plan;
+pub mod repodb;
+pub mod source;
+pub mod srcinfo;
+pub mod stages;
+pub mod version;
+pub mod why;
 
 const ROOT_DIR: &str = "/";
 const DB_PATH: &str = "/var/lib/pacman/";
+const DEFAULT_REPOS: [&str; 3] = ["core", "extra", "multilib"];
+const DEFAULT_ARCH: &str = "x86_64";
 
-/// Attempt to find any match of a package in the syncdb.
-fn find_package_anywhere<'a>(pkgname: &str, pacman: &'a alpm::Alpm) -> Result<&'a Package> {
-    let dbs = pacman.syncdbs();
-    for db in dbs {
-        if let Ok(pkg) = db.pkg(pkgname) {
-            return Ok(pkg);
-        }
+/// The path to the pacman database, `dbpath` or `var/lib/pacman` in the root directory `root` or
+/// the default ( /var/lib/pacman ).
+pub(crate) fn db_path(root: Option<&str>, dbpath: Option<&str>) -> String {
+    match (root, dbpath) {
+        (_, Some(dbpath)) => dbpath.to_string(),
+        (Some(root), None) => Path::new(root)
+            .join("var/lib/pacman/")
+            .display()
+            .to_string(),
+        (None, None) => DB_PATH.to_string(),
     }
-    Err(anyhow!(RebuildOrderError::PackageNotFound))
 }
 
-/// Retrieve a HashMap of all reverse dependencies.
-fn get_reverse_deps_map(
-    pacman: &alpm::Alpm,
-    with_check_depends: bool,
-) -> HashMap<String, HashSet<String>> {
-    let mut reverse_deps: HashMap<String, HashSet<String>> = HashMap::new();
-    let dbs = pacman.syncdbs();
-
-    for db in dbs {
-        for pkg in db.pkgs() {
-            for dep in pkg.depends() {
-                reverse_deps
-                    .entry(dep.name().to_string())
-                    .and_modify(|e| {
-                        e.insert(pkg.name().to_string());
-                    })
-                    .or_insert_with(|| {
-                        let mut modify = HashSet::new();
-                        modify.insert(pkg.name().to_string());
-                        modify
-                    });
-            }
-
-            for dep in pkg.makedepends() {
-                reverse_deps
-                    .entry(dep.name().to_string())
-                    .and_modify(|e| {
-                        e.insert(pkg.name().to_string());
-                    })
-                    .or_insert_with(|| {
-                        let mut modify = HashSet::new();
-                        modify.insert(pkg.name().to_string());
-                        modify
-                    });
-            }
-
-            if with_check_depends {
-                for dep in pkg.checkdepends() {
-                    reverse_deps
-                        .entry(dep.name().to_string())
-                        .and_modify(|e| {
-                            e.insert(pkg.name().to_string());
-                        })
-                        .or_insert_with(|| {
-                            let mut modify = HashSet::new();
-                            modify.insert(pkg.name().to_string());
-                            modify
-                        });
-                }
-            }
+/// The kind of dependency a reverse dependency has on a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum DependKind {
+    /// Runtime dependency (depends)
+    Depends,
+    /// Build dependency (makedepends)
+    MakeDepends,
+    /// Test dependency (checkdepends)
+    CheckDepends,
+}
+
+impl fmt::Display for DependKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DependKind::Depends => write!(f, "depends"),
+            DependKind::MakeDepends => write!(f, "makedepends"),
+            DependKind::CheckDepends => write!(f, "checkdepends"),
         }
     }
-
-    reverse_deps
 }
 
-/// Write a given DiGraph to a given file using a buffered writer.
-fn write_dotfile(filename: String, graph: DiGraph<&str, u16>) -> Result<()> {
-    let dotgraph = Dot::with_config(&graph, &[Config::EdgeNoLabel]);
-    let file = File::create(filename)?;
-    let mut bufw = BufWriter::new(file);
-    bufw.write_all(dotgraph.to_string().as_bytes())?;
+/// A dependency of a reverse dependency on a package in the rebuild graph.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Dependency {
+    /// The kind of dependency
+    pub kind: DependKind,
+    /// The provided name depended on, such as a soname, when it is not the package name
+    pub provide: Option<String>,
+}
 
-    Ok(())
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.provide {
+            Some(provide) => write!(f, "{} via {}", self.kind, provide),
+            None => write!(f, "{}", self.kind),
+        }
+    }
 }
 
-/// Run arch-rebuild-order, returning the rebuild order of provided package(s).
-pub fn run(
+/// Graph of packages where an edge points from a package to its reverse dependency.
+pub type RebuildGraph = DiGraph<String, Dependency>;
+
+/// Options of arch-rebuild-order, created with the provided package(s) and set by name.
+///
+/// ```no_run
+/// use arch_rebuild_order::RebuildOrderOptions;
+///
+/// let options = RebuildOrderOptions::new(vec!["opencolorio".to_string()])
+///     .repos(vec!["extra".to_string()])
+///     .pkgbase(true);
+/// let plan = arch_rebuild_order::run(&options).unwrap();
+/// println!("{}", plan.format_stages());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RebuildOrderOptions {
     pkgnames: Vec<String>,
+    root: Option<String>,
     dbpath: Option<String>,
     repos: Vec<String>,
+    layers: Vec<String>,
+    duplicates: DuplicatePolicy,
     dotfile: Option<String>,
     no_reverse_depends: bool,
+    depends: bool,
+    all: bool,
     with_check_depends: bool,
-) -> Result<String> {
-    let pacman = match dbpath {
-        Some(path) => alpm::Alpm::new(ROOT_DIR, &path),
-        None => alpm::Alpm::new(ROOT_DIR, DB_PATH),
+    pkgbase: bool,
+    soname: bool,
+    bumps: Vec<(String, String)>,
+    why: Option<String>,
+    all_chains: bool,
+    cache: bool,
+    db_files: Vec<(String, String)>,
+    srcinfo: Option<String>,
+    srcinfo_only: bool,
+    arch: String,
+    built_packages: Option<String>,
+}
+
+impl RebuildOrderOptions {
+    /// Create options for the provided package(s) using the core, extra and multilib
+    /// repositories.
+    pub fn new(pkgnames: Vec<String>) -> Self {
+        RebuildOrderOptions {
+            pkgnames,
+            root: None,
+            dbpath: None,
+            repos: DEFAULT_REPOS.iter().map(|x| x.to_string()).collect(),
+            layers: Vec::new(),
+            duplicates: DuplicatePolicy::RepoOrder,
+            dotfile: None,
+            no_reverse_depends: false,
+            depends: false,
+            all: false,
+            with_check_depends: false,
+            pkgbase: false,
+            soname: false,
+            bumps: Vec::new(),
+            why: None,
+            all_chains: false,
+            cache: false,
+            db_files: Vec::new(),
+            srcinfo: None,
+            srcinfo_only: false,
+            arch: DEFAULT_ARCH.to_string(),
+            built_packages: None,
+        }
     }
-    .map_err(RebuildOrderError::PacmanDbInit)?;
 
-    for repo in repos {
-        let _repo = pacman.register_syncdb(repo, SigLevel::DATABASE_OPTIONAL);
+    /// Use the `RootDir`, `DBPath`, repositories and `Architecture` of the given pacman.conf,
+    /// settings not in it are left unchanged
+    pub fn pacman_conf(mut self, conf: &PacmanConf) -> Self {
+        if conf.root_dir.is_some() {
+            self.root = conf.root_dir.clone();
+        }
+        if conf.db_path.is_some() {
+            self.dbpath = conf.db_path.clone();
+        }
+        if !conf.repos.is_empty() {
+            self.repos = conf.repos.clone();
+        }
+        if let Some(arch) = &conf.architecture {
+            self.arch = arch.clone();
+        }
+        self
     }
 
-    let reverse_deps_map = get_reverse_deps_map(&pacman, with_check_depends);
-    let mut provides = Vec::new();
-    let mut provides_map = HashMap::new();
+    /// The root directory, default ( / )
+    pub fn root(mut self, root: Option<String>) -> Self {
+        self.root = root;
+        self
+    }
 
-    for pkg in &pkgnames {
-        let repopkg = find_package_anywhere(pkg, &pacman)?;
-        for provide in repopkg.provides() {
-            provides.push(provide.name());
-            provides_map.insert(provide.name(), repopkg.name());
-        }
+    /// The path to the pacman database, default ( /var/lib/pacman ) in the root directory
+    pub fn dbpath(mut self, dbpath: Option<String>) -> Self {
+        self.dbpath = dbpath;
+        self
+    }
+
+    /// The repositories to retrieve the package information from
+    pub fn repos(mut self, repos: Vec<String>) -> Self {
+        self.repos = repos;
+        self
+    }
+
+    /// Layer repositories named `{repo}-{layer}` over every repository, highest priority first
+    /// such as `staging` and `testing`, their packages shadow the packages of the same name in
+    /// the layers below
+    pub fn layers(mut self, layers: Vec<String>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// How to choose between packages of the same name in several repositories, default the
+    /// first repository containing it
+    pub fn duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Write a dotfile of the rebuild graph into the given file
+    pub fn dotfile(mut self, dotfile: Option<String>) -> Self {
+        self.dotfile = dotfile;
+        self
+    }
+
+    /// Only output the provided package(s)
+    pub fn no_reverse_depends(mut self, no_reverse_depends: bool) -> Self {
+        self.no_reverse_depends = no_reverse_depends;
+        self
+    }
+
+    /// Order the dependencies (depends and makedepends) of the provided package(s) instead of
+    /// their reverse dependencies, every dependency is built before the packages depending on it.
+    /// Cannot be used with `soname`, `bumps` or `no_reverse_depends`
+    pub fn depends(mut self, depends: bool) -> Self {
+        self.depends = depends;
+        self
+    }
+
+    /// Order every package of the repositories instead of the provided package(s), each one
+    /// after its dependencies. Cannot be used with provided package(s), `soname`, `bumps` or
+    /// `no_reverse_depends`
+    pub fn all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    /// Include checkdepends
+    pub fn with_check_depends(mut self, with_check_depends: bool) -> Self {
+        self.with_check_depends = with_check_depends;
+        self
+    }
+
+    /// Merge split packages into one node per pkgbase
+    pub fn pkgbase(mut self, pkgbase: bool) -> Self {
+        self.pkgbase = pkgbase;
+        self
+    }
+
+    /// Only rebuild reverse dependencies depending on a soname provided by the provided package(s)
+    pub fn soname(mut self, soname: bool) -> Self {
+        self.soname = soname;
+        self
+    }
+
+    /// Bump packages to new versions, given as pairs of package name and version
+    pub fn bumps(mut self, bumps: Vec<(String, String)>) -> Self {
+        self.bumps = bumps;
+        self
+    }
+
+    /// Output the dependency chains to the given package instead of the rebuild order
+    pub fn why(mut self, why: Option<String>) -> Self {
+        self.why = why;
+        self
+    }
+
+    /// Output every dependency chain instead of the shortest with `why`
+    pub fn all_chains(mut self, all_chains: bool) -> Self {
+        self.all_chains = all_chains;
+        self
+    }
+
+    /// Load the index from the cache in `$XDG_CACHE_HOME` when the syncdbs are unchanged and
+    /// write it to the cache otherwise
+    pub fn cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Read the given repository database files, as pairs of repository name and path, instead
+    /// of the syncdbs of the `dbpath` and `repos` options
+    pub fn db_files(mut self, db_files: Vec<(String, String)>) -> Self {
+        self.db_files = db_files;
+        self
+    }
+
+    /// Overlay the packages of the `.SRCINFO` files in the given directory, one directory per
+    /// pkgbase, on top of the repositories
+    pub fn srcinfo(mut self, srcinfo: Option<String>) -> Self {
+        self.srcinfo = srcinfo;
+        self
+    }
+
+    /// Only use the packages of the `.SRCINFO` files instead of overlaying them on the
+    /// repositories
+    pub fn srcinfo_only(mut self, srcinfo_only: bool) -> Self {
+        self.srcinfo_only = srcinfo_only;
+        self
+    }
+
+    /// The architecture to read architecture specific `.SRCINFO` keys for, default ( x86_64 )
+    pub fn arch(mut self, arch: String) -> Self {
+        self.arch = arch;
+        self
     }
 
-    let mut graph = DiGraph::<&str, u16>::new();
-
-    let mut to_visit = VecDeque::new();
-    let mut to_build = HashSet::new();
-
-    to_visit.extend(pkgnames.iter().map(|x| x.as_str()));
-    to_visit.extend(provides.iter());
-
-    let mut cache_node: HashMap<&str, petgraph::graph::NodeIndex> = HashMap::new();
-
-    while !to_visit.is_empty() {
-        let pkg = if let Some(pkg) = to_visit.pop_front() {
-            pkg
-        } else {
-            break;
-        };
-
-        // Resolve the provided package to the real package as provided packages are not real
-        // packages in the Arch Linux repository.
-        let rootpkg = provides_map.get(&pkg).unwrap_or(&pkg);
-        let root = *cache_node
-            .entry(rootpkg)
-            .or_insert_with(|| graph.add_node(rootpkg));
-
-        if let Some(rev_deps_for_pkg) = reverse_deps_map.get(pkg) {
-            if !to_build.contains(&pkg.to_string()) {
-                to_visit.extend(rev_deps_for_pkg.iter().map(|x| x.as_str()));
-            }
-
-            let mut rev_deps_for_pkg_vec = rev_deps_for_pkg.iter().collect::<Vec<_>>();
-            rev_deps_for_pkg_vec.sort();
-
-            for rev_dep in rev_deps_for_pkg_vec {
-                let depnode = *cache_node
-                    .entry(rev_dep.as_str())
-                    .or_insert_with(|| graph.add_node(rev_dep));
-                if !graph.contains_edge(root, depnode) {
-                    graph.add_edge(root, depnode, 1);
-                }
-            }
-            to_build.extend(rev_deps_for_pkg);
-        };
-    }
-
-    // Visit nodes in our graph in a depth-first-search adding nodes in post-order. The provided
-    // packages are added first to the stack.
-    let mut rebuild_order_packages = Vec::new();
-    let mut bfs = DfsPostOrder::empty(&graph);
-    bfs.stack.extend(
-        pkgnames
+    /// Overlay the packages built in the given directory on top of the repositories, replacing
+    /// the packages of the same name
+    pub fn built_packages(mut self, built_packages: Option<String>) -> Self {
+        self.built_packages = built_packages;
+        self
+    }
+}
+
+impl RebuildOrderOptions {
+    /// The repositories with their layers, in priority order.
+    fn layered_repos(&self) -> Vec<String> {
+        self.repos
             .iter()
-            .filter_map(|pkg| cache_node.get(pkg.as_str())),
-    );
+            .flat_map(|repo| {
+                self.layers
+                    .iter()
+                    .map(move |layer| format!("{repo}-{layer}"))
+                    .chain([repo.clone()])
+            })
+            .collect()
+    }
+}
 
-    while let Some(nx) = bfs.next(&graph) {
-        let node = graph[nx];
-        rebuild_order_packages.push(node);
+/// Find the node of the given package in the rebuild graph.
+pub(crate) fn find_node(graph: &RebuildGraph, pkgname: &str) -> Option<NodeIndex> {
+    graph.node_indices().find(|&nx| graph[nx] == pkgname)
+}
+
+/// A name with multiple providers which reverse dependencies were followed through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmbiguousProvide {
+    /// The provided name
+    pub name: String,
+    /// The packages providing the name, sorted by name
+    pub providers: Vec<String>,
+}
+
+impl fmt::Display for AmbiguousProvide {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is provided by multiple packages: {}",
+            self.name,
+            self.providers.join(", ")
+        )
     }
+}
 
-    // Reverse the rebuild order as DfsPostOrder starts with the first pkgname and therefore
-    // shows it as last package
-    rebuild_order_packages.reverse();
+/// Metadata of a package in the rebuild graph.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageInfo {
+    /// The pkgbase of the package
+    pub pkgbase: String,
+    /// The repository the package is in
+    pub repo: String,
+    /// The version of the package
+    pub version: String,
+    /// The architecture of the package
+    pub arch: Option<String>,
+}
 
-    // We only retain the packages we want to when using `--no-reverse_depends`
-    // This logic is hard to parse because retain is an inverse filter,
-    // thus we use the negated form of: no_reverse_depends && !pkgnames.contains(&pkg.to_string()
-    rebuild_order_packages.retain(|pkg| !no_reverse_depends || pkgnames.contains(&pkg.to_string()));
+/// The rebuild graph of provided package(s).
+#[derive(Debug, Clone)]
+pub struct Rebuild {
+    /// Graph of the packages to rebuild
+    pub graph: RebuildGraph,
+    /// The provided packages as they are named in the graph
+    pub pkgnames: Vec<String>,
+    /// Provided names with multiple providers, sorted by name
+    pub ambiguous_provides: Vec<AmbiguousProvide>,
+    /// Version constraints broken by bumped packages
+    pub broken_constraints: Vec<BrokenConstraint>,
+    /// Metadata of every package in the graph, a pkgbase is described by its first split package
+    pub packages: HashMap<String, PackageInfo>,
+}
+
+/// A version constraint of a reverse dependency which is no longer satisfied by a bumped package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenConstraint {
+    /// The bumped package
+    pub pkgname: String,
+    /// The new version of the bumped package
+    pub version: String,
+    /// The reverse dependency, a pkgbase for build time dependencies
+    pub rev_dep: String,
+    /// The kind of dependency
+    pub kind: DependKind,
+    /// The version constraint not satisfied by the new version
+    pub constraint: Constraint,
+}
+
+impl fmt::Display for BrokenConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "bumping {} to {} breaks {} {} {}{}",
+            self.pkgname, self.version, self.rev_dep, self.kind, self.pkgname, self.constraint
+        )
+    }
+}
+
+/// Build the index of the `db_files` option or of the `dbpath` and `repos` options, or load the
+/// latter from the cache. The packages of the `built_packages` option and then those of the
+/// `srcinfo` option are overlaid on top, with `srcinfo_only` the repositories are left out.
+fn open_index(options: &RebuildOrderOptions) -> Result<DependencyIndex> {
+    if options.srcinfo.is_none() && options.built_packages.is_none() {
+        return open_repos(options);
+    }
+
+    let mut source: Box<dyn PackageSource> = if options.srcinfo.is_some() && options.srcinfo_only {
+        Box::new(MemorySource::default())
+    } else if options.db_files.is_empty() {
+        Box::new(AlpmSource::new(
+            options.root.as_deref(),
+            options.dbpath.as_deref(),
+            &options.layered_repos(),
+        )?)
+    } else {
+        Box::new(RepoDbSource::open(&options.db_files)?)
+    };
+
+    if let Some(built_packages) = &options.built_packages {
+        let built_packages = PkginfoSource::open(Path::new(built_packages))?;
+        source = Box::new(OverlaySource::per_package(source, Box::new(built_packages)));
+    }
 
-    if let Some(filename) = dotfile {
-        write_dotfile(filename, graph)?;
+    if let Some(srcinfo) = &options.srcinfo {
+        let srcinfo = SrcinfoSource::open(Path::new(srcinfo), &options.arch)?;
+        source = Box::new(OverlaySource::new(source, Box::new(srcinfo)));
     }
 
-    Ok(rebuild_order_packages.join(" "))
+    Ok(DependencyIndex::from_source_with_policy(
+        source.as_ref(),
+        options.duplicates,
+    ))
+}
+
+/// Build the index of the repositories of the `db_files` option or of the `dbpath` and `repos`
+/// options, or load the latter from the cache.
+fn open_repos(options: &RebuildOrderOptions) -> Result<DependencyIndex> {
+    if !options.db_files.is_empty() {
+        let source = RepoDbSource::open(&options.db_files)?;
+        return Ok(DependencyIndex::from_source_with_policy(
+            &source,
+            options.duplicates,
+        ));
+    }
+
+    let (root, dbpath) = (options.root.as_deref(), options.dbpath.as_deref());
+    let repos = options.layered_repos();
+    match options.cache.then(cache::cache_dir).flatten() {
+        Some(cache_dir) => {
+            cache::load_or_build(&cache_dir, root, dbpath, &repos, options.duplicates)
+        }
+        None => DependencyIndex::with_root(root, dbpath, &repos, options.duplicates),
+    }
+}
+
+/// Build the rebuild graph of provided package(s) from the given repositories.
+///
+/// Reverse dependencies are followed by package name and by every name a package provides. With
+/// `soname` only reverse dependencies depending on a soname provided by the provided package(s)
+/// are rebuild candidates, the soname is recorded as provide of their dependency. With `pkgbase`
+/// every split package is merged into a node for its pkgbase.
+///
+/// Versioned dependencies are only followed when satisfied by the version of the package or
+/// provide, bumped packages satisfy them with their new version as well.
+///
+/// With `depends` the dependencies of the provided package(s) are followed instead, every
+/// dependency resolved to the package of that name or the first provider by name, edges still
+/// point from a package to the package depending on it. With `all` every package of the index is
+/// provided and ordered after its dependencies.
+///
+/// The index is built from the `db_files` option or the `dbpath` and `repos` options, the
+/// `built_packages` and the `srcinfo` option, use [`DependencyIndex::rebuild_graph`] to answer
+/// multiple queries from the same index.
+pub fn rebuild_graph(options: &RebuildOrderOptions) -> Result<Rebuild> {
+    open_index(options)?.rebuild_graph(options)
+}
+
+/// Run arch-rebuild-order, returning the rebuild plan of provided package(s).
+///
+/// With `why` the plan holds the dependency chains from the provided package(s) to the given
+/// package, the shortest chain or with `all_chains` every chain. The index is built from the
+/// `db_files` option or the `dbpath` and `repos` options, the `built_packages` and the `srcinfo`
+/// option, use [`DependencyIndex::run`] to answer multiple queries from the same index.
+pub fn run(options: &RebuildOrderOptions) -> Result<RebuildPlan> {
+    open_index(options)?.run(options)
 }
@@ -1,17 +1,122 @@
+use anyhow::Result;
 use clap::Parser;
+use std::path::Path;
 
-use arch_rebuild_order::args::Args;
+use arch_rebuild_order::args::{Args, OutputFormat};
+use arch_rebuild_order::config::PacmanConf;
+use arch_rebuild_order::plan::RebuildPlan;
+use arch_rebuild_order::RebuildOrderOptions;
+
+/// Print the warnings of the rebuild plan to stderr.
+fn print_warnings(plan: &RebuildPlan) {
+    for ambiguous_provide in &plan.ambiguous_provides {
+        eprintln!("warning: {ambiguous_provide}");
+    }
+
+    for broken_constraint in &plan.broken_constraints {
+        eprintln!("warning: {broken_constraint}");
+    }
+
+    for cycle in &plan.cycles {
+        eprintln!("warning: {cycle}");
+    }
+
+    for (pkg, rev_dep, kind) in &plan.dropped {
+        eprintln!("warning: ignoring {rev_dep} {kind} on {pkg} to break a dependency cycle");
+    }
+
+    for shadowed in &plan.shadowed {
+        eprintln!("note: {shadowed}");
+    }
+}
+
+/// Format the rebuild plan as requested by the arguments.
+fn format_plan(plan: &RebuildPlan, args: &Args) -> Result<String> {
+    let output = if args.format == OutputFormat::Json {
+        arch_rebuild_order::json::to_json(plan)?
+    } else if args.why.is_some() {
+        plan.chains
+            .iter()
+            .map(|chain| chain.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    } else if args.stages {
+        plan.format_stages()
+    } else {
+        plan.to_string()
+    };
+
+    Ok(output)
+}
+
+/// Build the options from the arguments, on top of the settings of the pacman.conf given with
+/// --config.
+fn build_options(args: &Args) -> Result<RebuildOrderOptions> {
+    let mut options = RebuildOrderOptions::new(args.pkgnames.clone());
+    if let Some(config) = &args.config {
+        options = options.pacman_conf(&PacmanConf::parse_file(Path::new(config))?);
+    }
+    if args.root.is_some() {
+        options = options.root(args.root.clone());
+    }
+    if args.dbpath.is_some() {
+        options = options.dbpath(args.dbpath.clone());
+    }
+    if let Some(repos) = &args.repos {
+        options = options.repos(repos.clone());
+    }
+    if args.staging {
+        options = options.layers(vec!["staging".to_string(), "testing".to_string()]);
+    } else if args.testing {
+        options = options.layers(vec!["testing".to_string()]);
+    }
+    if let Some(arch) = &args.arch {
+        options = options.arch(arch.clone());
+    }
+
+    Ok(options
+        .duplicates(args.duplicates)
+        .dotfile(args.dotfile.clone())
+        .no_reverse_depends(args.no_reverse_depends)
+        .depends(args.depends)
+        .all(args.all)
+        .with_check_depends(args.with_check_depends)
+        .pkgbase(args.pkgbase)
+        .soname(args.soname)
+        .bumps(args.bump.clone())
+        .why(args.why.clone())
+        .all_chains(args.all_chains)
+        .cache(!args.no_cache)
+        .db_files(args.db_file.clone())
+        .srcinfo(args.srcinfo.clone())
+        .srcinfo_only(args.srcinfo_only)
+        .built_packages(args.built_packages.clone()))
+}
 
 fn main() {
     let args = Args::parse();
-    match arch_rebuild_order::run(
-        args.pkgnames,
-        args.dbpath,
-        args.repos,
-        args.dotfile,
-        args.no_reverse_depends,
-        args.with_check_depends,
-    ) {
+
+    if args.clear_cache {
+        if let Some(cache_dir) = arch_rebuild_order::cache::cache_dir() {
+            if let Err(e) = arch_rebuild_order::cache::clear(&cache_dir) {
+                eprintln!("Critical failure - arch-rebuild-order has stopped working");
+                eprintln!("Reason: {}", e);
+                std::process::exit(1);
+            }
+        }
+        if args.pkgnames.is_empty() && !args.all {
+            std::process::exit(0);
+        }
+    }
+
+    let output = build_options(&args)
+        .and_then(|options| arch_rebuild_order::run(&options))
+        .and_then(|plan| {
+            print_warnings(&plan);
+            format_plan(&plan, &args)
+        });
+
+    match output {
         Ok(output) => {
             println!("{output}");
             std::process::exit(0);
@@ -0,0 +1,105 @@
+//! Reader for the `.PKGINFO` of built packages such as `foo-1.0-1-x86_64.pkg.tar.zst`.
+
+use crate::error::RebuildOrderError;
+use crate::repodb::decompress;
+use crate::source::{Depend, PackageSource, SourcePackage};
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Packages read from the built packages in a directory.
+#[derive(Debug, Clone, Default)]
+pub struct PkginfoSource {
+    packages: Vec<SourcePackage>,
+}
+
+impl PkginfoSource {
+    /// Read the `.PKGINFO` of every `*.pkg.tar*` file in `dir`, keeping the highest version of
+    /// every package, sorted by package name.
+    pub fn open(dir: &Path) -> Result<PkginfoSource> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .map_err(|e| RebuildOrderError::read("built package", dir, e))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                path.is_file() && name.contains(".pkg.tar") && !name.ends_with(".sig")
+            })
+            .collect();
+        paths.sort();
+
+        let mut packages: HashMap<String, SourcePackage> = HashMap::new();
+        for path in paths {
+            let read_error = |e| RebuildOrderError::read("built package", &path, e);
+            let file = File::open(&path).map_err(read_error)?;
+            let pkg = read_pkginfo(file).map_err(read_error)?;
+            match packages.get(&pkg.name) {
+                Some(other)
+                    if alpm::vercmp(pkg.version.as_str(), other.version.as_str())
+                        != Ordering::Greater => {}
+                _ => {
+                    packages.insert(pkg.name.clone(), pkg);
+                }
+            }
+        }
+
+        let mut packages: Vec<SourcePackage> = packages.into_values().collect();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(PkginfoSource { packages })
+    }
+}
+
+impl PackageSource for PkginfoSource {
+    fn packages(&self) -> &[SourcePackage] {
+        &self.packages
+    }
+}
+
+/// Read the package from the `.PKGINFO` of a built package.
+pub fn read_pkginfo(reader: impl Read) -> io::Result<SourcePackage> {
+    let mut archive = tar::Archive::new(decompress(reader)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() != ".PKGINFO" {
+            continue;
+        }
+
+        let mut pkginfo = String::new();
+        entry.read_to_string(&mut pkginfo)?;
+        return parse_pkginfo(&pkginfo)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, ".PKGINFO has no pkgname"));
+    }
+
+    Err(io::Error::new(io::ErrorKind::InvalidData, "no .PKGINFO"))
+}
+
+/// Parse the `key = value` lines of a `.PKGINFO`, returning `None` without a package name.
+fn parse_pkginfo(pkginfo: &str) -> Option<SourcePackage> {
+    let mut pkg = SourcePackage::default();
+
+    for line in pkginfo.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+
+        match key {
+            "pkgname" => pkg.name = value.to_string(),
+            "pkgbase" => pkg.base = Some(value.to_string()),
+            "pkgver" => pkg.version = value.to_string(),
+            "arch" => pkg.arch = Some(value.to_string()),
+            "depend" => pkg.depends.push(Depend::parse(value)),
+            "makedepend" => pkg.makedepends.push(Depend::parse(value)),
+            "checkdepend" => pkg.checkdepends.push(Depend::parse(value)),
+            "provides" => pkg.provides.push(Depend::parse(value)),
+            _ => {}
+        }
+    }
+
+    (!pkg.name.is_empty()).then_some(pkg)
+}
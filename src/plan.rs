@@ -0,0 +1,466 @@
+use crate::cycles::{self, BuildGraph, DependencyCycle};
+use crate::error::RebuildOrderError;
+use crate::index::{DependencyIndex, ShadowedPackage};
+use crate::stages;
+use crate::why::{self, Chain};
+use crate::{
+    AmbiguousProvide, BrokenConstraint, DependKind, Dependency, PackageInfo, Rebuild, RebuildGraph,
+    RebuildOrderOptions,
+};
+use anyhow::Result;
+use petgraph::dot::{Config, Dot};
+use petgraph::graph::{EdgeReference, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Why a package is part of the rebuild plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// The package was provided as input
+    Provided,
+    /// The package is a reverse dependency of a package closer to the provided package(s)
+    ReverseDependency {
+        /// The package depended on
+        pkgname: String,
+        /// The dependency on the package
+        dependency: Dependency,
+    },
+    /// The package is a dependency of a package closer to the provided package(s)
+    Dependency {
+        /// The package depending on it
+        pkgname: String,
+        /// The dependency on the package
+        dependency: Dependency,
+    },
+    /// The package is a split package built together with a package of the same pkgbase
+    SplitPackage {
+        /// The package of the same pkgbase
+        pkgname: String,
+    },
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Provided => write!(f, "provided"),
+            Reason::ReverseDependency {
+                pkgname,
+                dependency,
+            } => write!(f, "reverse dependency of {pkgname} ({dependency})"),
+            Reason::Dependency {
+                pkgname,
+                dependency,
+            } => write!(f, "dependency of {pkgname} ({dependency})"),
+            Reason::SplitPackage { pkgname } => write!(f, "split package of {pkgname}"),
+        }
+    }
+}
+
+/// A package to rebuild.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildEntry {
+    /// The package name, or the pkgbase when merging split packages
+    pub pkgname: String,
+    /// The pkgbase of the package
+    pub pkgbase: String,
+    /// The repository the package is in
+    pub repo: String,
+    /// The version of the package
+    pub version: String,
+    /// The architecture of the package
+    pub arch: Option<String>,
+    /// The number of dependency hops from the provided package(s)
+    pub depth: usize,
+    /// The stage of the package, starting at zero without empty stages, packages of a stage can be
+    /// built in parallel
+    pub stage: usize,
+    /// Why the package is rebuilt
+    pub reason: Reason,
+    /// Whether this is the bootstrap build of a package in a dependency cycle
+    pub bootstrap: bool,
+}
+
+impl fmt::Display for RebuildEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.bootstrap {
+            write!(f, "{}:bootstrap", self.pkgname)
+        } else {
+            write!(f, "{}", self.pkgname)
+        }
+    }
+}
+
+/// The plan to rebuild provided package(s), displayed as the rebuild order.
+#[derive(Debug, Clone)]
+pub struct RebuildPlan {
+    /// The packages to rebuild in rebuild order
+    pub entries: Vec<RebuildEntry>,
+    /// The provided packages as they are named in the graph
+    pub pkgnames: Vec<String>,
+    /// Graph of the packages to rebuild, including dependency cycles
+    pub graph: RebuildGraph,
+    /// Dependency cycles in the graph
+    pub cycles: Vec<DependencyCycle>,
+    /// Dependencies ignored to break dependency cycles as package, reverse dependency and kind
+    pub dropped: Vec<(String, String, DependKind)>,
+    /// Provided names with multiple providers, sorted by name
+    pub ambiguous_provides: Vec<AmbiguousProvide>,
+    /// Version constraints broken by bumped packages
+    pub broken_constraints: Vec<BrokenConstraint>,
+    /// Dependency chains to the package asked about with `why`
+    pub chains: Vec<Chain>,
+    /// Packages of the rebuild graph shadowed by a package of the same name in another
+    /// repository
+    pub shadowed: Vec<ShadowedPackage>,
+}
+
+impl RebuildPlan {
+    /// Group the entries into stages sorted by name, leaving out empty stages. Packages of a stage
+    /// are built in parallel, so unlike the entries they are not ordered by provided packages and
+    /// repository priority.
+    pub fn stages(&self) -> Vec<Vec<&RebuildEntry>> {
+        let mut stages: Vec<Vec<&RebuildEntry>> = Vec::new();
+        for entry in &self.entries {
+            if stages.len() <= entry.stage {
+                stages.resize_with(entry.stage + 1, Vec::new);
+            }
+            stages[entry.stage].push(entry);
+        }
+
+        stages.retain(|stage| !stage.is_empty());
+        for stage in &mut stages {
+            stage.sort_by(|a, b| a.pkgname.cmp(&b.pkgname));
+        }
+        stages
+    }
+
+    /// Format the stages, one numbered stage per line.
+    pub fn format_stages(&self) -> String {
+        self.stages()
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| {
+                let entries: Vec<String> = stage.iter().map(|x| x.to_string()).collect();
+                format!("stage {}: {}", i + 1, entries.join(" "))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for RebuildPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries: Vec<String> = self.entries.iter().map(|x| x.to_string()).collect();
+        write!(f, "{}", entries.join(" "))
+    }
+}
+
+/// Find the depth and reason of every package reachable from the provided package(s), following
+/// the shortest chain of reverse dependencies, or of dependencies against the edges with
+/// `Direction::Incoming`. Split packages are built together, so they share the depth of the first
+/// split package reached.
+pub(crate) fn reasons<'a>(
+    graph: &'a RebuildGraph,
+    pkgnames: &[String],
+    packages: &HashMap<String, PackageInfo>,
+    direction: Direction,
+) -> HashMap<&'a str, (usize, Reason)> {
+    let mut split_packages: HashMap<&str, Vec<NodeIndex>> = HashMap::new();
+    for nx in graph.node_indices() {
+        if let Some(info) = packages.get(&graph[nx]) {
+            split_packages.entry(&info.pkgbase).or_default().push(nx);
+        }
+    }
+
+    let mut reasons = HashMap::new();
+    let mut to_visit = VecDeque::new();
+
+    let nodes: HashMap<&str, NodeIndex> = graph
+        .node_indices()
+        .map(|nx| (graph[nx].as_str(), nx))
+        .collect();
+    for nx in pkgnames
+        .iter()
+        .filter_map(|pkg| nodes.get(pkg.as_str()).copied())
+    {
+        if let Entry::Vacant(entry) = reasons.entry(graph[nx].as_str()) {
+            entry.insert((0, Reason::Provided));
+            to_visit.push_back((nx, 0));
+        }
+    }
+
+    while let Some((nx, depth)) = to_visit.pop_front() {
+        let pkgbase = packages.get(&graph[nx]).map(|info| info.pkgbase.as_str());
+        for &split_package in pkgbase
+            .and_then(|x| split_packages.get(x))
+            .into_iter()
+            .flatten()
+        {
+            if let Entry::Vacant(entry) = reasons.entry(graph[split_package].as_str()) {
+                let reason = Reason::SplitPackage {
+                    pkgname: graph[nx].clone(),
+                };
+                entry.insert((depth, reason));
+                to_visit.push_front((split_package, depth));
+            }
+        }
+
+        let neighbor = |edge: &EdgeReference<Dependency>| match direction {
+            Direction::Outgoing => edge.target(),
+            Direction::Incoming => edge.source(),
+        };
+        let mut edges: Vec<_> = graph.edges_directed(nx, direction).collect();
+        edges.sort_by(|a, b| graph[neighbor(a)].cmp(&graph[neighbor(b)]));
+        for edge in edges {
+            if let Entry::Vacant(entry) = reasons.entry(graph[neighbor(&edge)].as_str()) {
+                let pkgname = graph[nx].clone();
+                let dependency = edge.weight().clone();
+                let reason = match direction {
+                    Direction::Outgoing => Reason::ReverseDependency {
+                        pkgname,
+                        dependency,
+                    },
+                    Direction::Incoming => Reason::Dependency {
+                        pkgname,
+                        dependency,
+                    },
+                };
+                entry.insert((depth + 1, reason));
+                to_visit.push_back((neighbor(&edge), depth + 1));
+            }
+        }
+    }
+
+    reasons
+}
+
+/// Write a given DiGraph to a given file using a buffered writer.
+fn write_dotfile(filename: &str, graph: &RebuildGraph) -> Result<()> {
+    let dotgraph = Dot::with_config(graph, &[Config::EdgeNoLabel]);
+    let file = File::create(filename)?;
+    let mut bufw = BufWriter::new(file);
+    bufw.write_all(dotgraph.to_string().as_bytes())?;
+
+    Ok(())
+}
+
+/// Return the rebuild graph with every split package depending on the dependencies of its
+/// siblings.
+///
+/// Split packages of the same pkgbase are built together and have no edges between them, so a
+/// split package which only depends on a sibling would otherwise be ordered before the
+/// dependencies of its pkgbase.
+fn with_pkgbase_depends(
+    graph: &RebuildGraph,
+    packages: &HashMap<String, PackageInfo>,
+) -> RebuildGraph {
+    let mut split_packages: HashMap<&str, Vec<NodeIndex>> = HashMap::new();
+    for nx in graph.node_indices() {
+        if let Some(info) = packages.get(&graph[nx]) {
+            split_packages.entry(&info.pkgbase).or_default().push(nx);
+        }
+    }
+
+    let mut pkgbase_graph = graph.clone();
+    for edge in graph.edge_references() {
+        let pkgbase = packages
+            .get(&graph[edge.target()])
+            .map(|info| info.pkgbase.as_str());
+        for &split_package in pkgbase
+            .and_then(|x| split_packages.get(x))
+            .into_iter()
+            .flatten()
+        {
+            if split_package != edge.source()
+                && !pkgbase_graph.contains_edge(edge.source(), split_package)
+            {
+                pkgbase_graph.add_edge(edge.source(), split_package, edge.weight().clone());
+            }
+        }
+    }
+
+    pkgbase_graph
+}
+
+/// Order the acyclic build graph with Kahn's algorithm, returning the package names and whether
+/// it is a bootstrap build.
+///
+/// Of the packages whose dependencies are all ordered, the provided packages come first, then the
+/// packages of the repository with the highest priority and then by name, followed by the final
+/// build of a bootstrapped package. The order only depends on the graph and never on the order
+/// the packages were read in.
+fn build_order<'a>(
+    build_graph: &'a BuildGraph,
+    pkgnames: &[String],
+    repo_priority: &HashMap<&str, usize>,
+) -> Vec<(&'a str, bool)> {
+    let graph = &build_graph.graph;
+    let pkgnames: HashSet<&str> = pkgnames.iter().map(|x| x.as_str()).collect();
+    let key = |nx: NodeIndex| {
+        let pkg = graph[nx].as_str();
+        Reverse((
+            !pkgnames.contains(pkg),
+            repo_priority.get(pkg).copied().unwrap_or(usize::MAX),
+            pkg,
+            !build_graph.bootstrap.contains(&nx),
+            nx,
+        ))
+    };
+
+    let mut in_degree: Vec<usize> = graph
+        .node_indices()
+        .map(|nx| graph.edges_directed(nx, Direction::Incoming).count())
+        .collect();
+    let mut ready: BinaryHeap<_> = graph
+        .node_indices()
+        .filter(|nx| in_degree[nx.index()] == 0)
+        .map(key)
+        .collect();
+
+    let mut order = Vec::new();
+    while let Some(Reverse((_, _, pkg, final_build, nx))) = ready.pop() {
+        order.push((pkg, !final_build));
+        for edge in graph.edges(nx) {
+            let target = edge.target();
+            in_degree[target.index()] -= 1;
+            if in_degree[target.index()] == 0 {
+                ready.push(key(target));
+            }
+        }
+    }
+
+    order
+}
+
+impl DependencyIndex {
+    /// Run arch-rebuild-order on the index, returning the rebuild plan of provided package(s) and
+    /// ignoring the `dbpath` and `repos` options. Repositories are prioritized in the order of
+    /// the index.
+    pub fn run(&self, options: &RebuildOrderOptions) -> Result<RebuildPlan> {
+        let Rebuild {
+            graph,
+            pkgnames,
+            ambiguous_provides,
+            broken_constraints,
+            packages,
+        } = self.rebuild_graph(options)?;
+
+        // Dependency chains follow the edges from the provided package(s), which point towards
+        // them when ordering dependencies.
+        let (direction, chain_graph) = if options.depends {
+            let mut reversed = graph.clone();
+            reversed.reverse();
+            (Direction::Incoming, Cow::Owned(reversed))
+        } else {
+            (Direction::Outgoing, Cow::Borrowed(&graph))
+        };
+        // Split packages are merged into the node of their pkgbase with `pkgbase`.
+        let target = options.why.as_deref().map(|target| {
+            if options.pkgbase {
+                self.pkgbase(target)
+            } else {
+                target
+            }
+        });
+        let chains = match target {
+            Some(target) if options.all_chains => {
+                why::all_chains(&chain_graph, &pkgnames, &packages, target)
+            }
+            Some(target) => why::shortest_chain(&chain_graph, &pkgnames, &packages, target)
+                .into_iter()
+                .collect(),
+            None => Vec::new(),
+        };
+        if let Some(target) = &options.why {
+            if chains.is_empty() {
+                return Err(RebuildOrderError::NotInRebuildGraph(target.clone()).into());
+            }
+        }
+
+        let build_graph = cycles::break_cycles(&with_pkgbase_depends(&graph, &packages), &pkgnames);
+
+        let mut stage_of = HashMap::new();
+        for (i, stage) in stages::build_stages(&build_graph).into_iter().enumerate() {
+            for entry in stage {
+                stage_of.insert(entry, i);
+            }
+        }
+        let reasons = reasons(&graph, &pkgnames, &packages, direction);
+
+        let repo_priority: HashMap<&str, usize> = packages
+            .iter()
+            .filter_map(|(pkg, info)| {
+                let priority = self.repos().iter().position(|repo| *repo == info.repo)?;
+                Some((pkg.as_str(), priority))
+            })
+            .collect();
+        let mut rebuild_order_packages = build_order(&build_graph, &pkgnames, &repo_priority);
+
+        // We only retain the packages we want to when using `--no-reverse_depends`
+        // This logic is hard to parse because retain is an inverse filter,
+        // thus we use the negated form of: no_reverse_depends && !pkgnames.contains(&pkg.to_string()
+        rebuild_order_packages
+            .retain(|(pkg, _)| !options.no_reverse_depends || pkgnames.contains(&pkg.to_string()));
+
+        // Stages left empty by `no_reverse_depends` are skipped, numbering the stages of the
+        // entries as `RebuildPlan::stages` does.
+        let mut stages: Vec<usize> = rebuild_order_packages
+            .iter()
+            .map(|entry| stage_of[entry])
+            .collect();
+        stages.sort();
+        stages.dedup();
+
+        let entries = rebuild_order_packages
+            .into_iter()
+            .map(|(pkg, bootstrap)| {
+                let info = &packages[pkg];
+                let (depth, reason) = reasons[pkg].clone();
+                RebuildEntry {
+                    pkgname: pkg.to_string(),
+                    pkgbase: info.pkgbase.clone(),
+                    repo: info.repo.clone(),
+                    version: info.version.clone(),
+                    arch: info.arch.clone(),
+                    depth,
+                    stage: stages.binary_search(&stage_of[&(pkg, bootstrap)]).unwrap(),
+                    reason,
+                    bootstrap,
+                }
+            })
+            .collect();
+
+        if let Some(filename) = &options.dotfile {
+            write_dotfile(filename, &graph)?;
+        }
+
+        let nodes: HashSet<&str> = graph.node_weights().map(|x| x.as_str()).collect();
+        let shadowed = self
+            .shadowed()
+            .iter()
+            .filter(|x| {
+                nodes.contains(x.pkgname.as_str()) || nodes.contains(self.pkgbase(&x.pkgname))
+            })
+            .cloned()
+            .collect();
+
+        Ok(RebuildPlan {
+            entries,
+            cycles: cycles::find_cycles(&graph),
+            dropped: build_graph.dropped,
+            pkgnames,
+            graph,
+            ambiguous_provides,
+            broken_constraints,
+            chains,
+            shadowed,
+        })
+    }
+}
@@ -0,0 +1,139 @@
+//! Reader for repository databases such as `extra.db`, without libalpm.
+//!
+//! A repository database is a tar archive, optionally compressed with gzip, xz or zstd, holding a
+//! `pkgname-pkgver/desc` entry per package. Older databases keep the dependencies in a separate
+//! `depends` entry, both are read.
+
+use crate::error::RebuildOrderError;
+use crate::source::{Depend, PackageSource, SourcePackage};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Packages read from repository database files.
+#[derive(Debug, Clone, Default)]
+pub struct RepoDbSource {
+    packages: Vec<SourcePackage>,
+}
+
+impl RepoDbSource {
+    /// Read the given repository database files, as pairs of repository name and path, in
+    /// repository order.
+    pub fn open(db_files: &[(String, String)]) -> Result<RepoDbSource> {
+        let mut packages = Vec::new();
+        for (repo, path) in db_files {
+            let read_error = |e| RebuildOrderError::read("repository database", Path::new(path), e);
+            let file = File::open(path).map_err(read_error)?;
+            packages.extend(read_db(repo, file).map_err(read_error)?);
+        }
+
+        Ok(RepoDbSource { packages })
+    }
+}
+
+impl PackageSource for RepoDbSource {
+    fn packages(&self) -> &[SourcePackage] {
+        &self.packages
+    }
+}
+
+/// Wrap the reader in a decoder for the compression detected from its magic bytes.
+pub(crate) fn decompress<'a>(reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(xz2::read::XzDecoder::new(reader))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Read the packages of a repository database of the given repository.
+pub fn read_db(repo: &str, reader: impl Read) -> io::Result<Vec<SourcePackage>> {
+    let mut archive = tar::Archive::new(decompress(reader)?);
+    let mut dirs: Vec<String> = Vec::new();
+    let mut entries: HashMap<String, String> = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?;
+        let (Some(dir), Some(file)) = (path.parent(), path.file_name()) else {
+            continue;
+        };
+        if file != "desc" && file != "depends" {
+            continue;
+        }
+        let dir = dir.to_string_lossy().into_owned();
+
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        match entries.get_mut(&dir) {
+            Some(desc) => {
+                // The last value of a file without trailing newline must not merge with the
+                // header of the next file.
+                desc.push('\n');
+                desc.push_str(&content);
+            }
+            None => {
+                dirs.push(dir.clone());
+                entries.insert(dir, content);
+            }
+        }
+    }
+
+    dirs.iter()
+        .map(|dir| {
+            parse_desc(repo, &entries[dir]).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{dir}/desc has no %NAME%"),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Parse the `%KEY%` sections of a `desc` entry, returning `None` without a package name.
+fn parse_desc(repo: &str, desc: &str) -> Option<SourcePackage> {
+    let mut pkg = SourcePackage {
+        repo: repo.to_string(),
+        ..Default::default()
+    };
+
+    let mut key = "";
+    for line in desc.lines().filter(|x| !x.is_empty()) {
+        if let Some(header) = line.strip_prefix('%').and_then(|x| x.strip_suffix('%')) {
+            key = header;
+            continue;
+        }
+
+        match key {
+            "NAME" => pkg.name = line.to_string(),
+            "BASE" => pkg.base = Some(line.to_string()),
+            "VERSION" => pkg.version = line.to_string(),
+            "ARCH" => pkg.arch = Some(line.to_string()),
+            "DEPENDS" => pkg.depends.push(Depend::parse(line)),
+            "MAKEDEPENDS" => pkg.makedepends.push(Depend::parse(line)),
+            "CHECKDEPENDS" => pkg.checkdepends.push(Depend::parse(line)),
+            "PROVIDES" => pkg.provides.push(Depend::parse(line)),
+            _ => {}
+        }
+    }
+
+    (!pkg.name.is_empty()).then_some(pkg)
+}
@@ -0,0 +1,258 @@
+use crate::error::RebuildOrderError;
+use crate::version::{Comparison, Constraint};
+use crate::{db_path, ROOT_DIR};
+use alpm::SigLevel;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A dependency on a name, optionally versioned such as `foo>=2`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Depend {
+    /// The name depended on
+    pub name: String,
+    /// The version constraint on the name
+    pub constraint: Option<Constraint>,
+}
+
+impl Depend {
+    /// Parse a dependency such as `foo`, `foo>=2` or `libfoo.so=1-64`.
+    pub fn parse(depend: &str) -> Depend {
+        let Some(pos) = depend.find(['<', '>', '=']) else {
+            return Depend {
+                name: depend.to_string(),
+                constraint: None,
+            };
+        };
+
+        let (name, rest) = depend.split_at(pos);
+        let (comparison, version) = if let Some(version) = rest.strip_prefix(">=") {
+            (Comparison::Ge, version)
+        } else if let Some(version) = rest.strip_prefix("<=") {
+            (Comparison::Le, version)
+        } else if let Some(version) = rest.strip_prefix('>') {
+            (Comparison::Gt, version)
+        } else if let Some(version) = rest.strip_prefix('<') {
+            (Comparison::Lt, version)
+        } else {
+            (Comparison::Eq, &rest[1..])
+        };
+
+        Depend {
+            name: name.to_string(),
+            constraint: Some(Constraint {
+                comparison,
+                version: version.to_string(),
+            }),
+        }
+    }
+
+    /// Convert an alpm dependency.
+    pub fn from_alpm(dep: &alpm::Dep) -> Depend {
+        Depend {
+            name: dep.name().to_string(),
+            constraint: Constraint::from_dep(dep),
+        }
+    }
+
+    /// The version of a provide such as `libfoo.so=1-64`.
+    pub fn provided_version(&self) -> Option<&str> {
+        match &self.constraint {
+            Some(constraint) if constraint.comparison == Comparison::Eq => {
+                Some(constraint.version.as_str())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Depend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.constraint {
+            Some(constraint) => write!(f, "{}{}", self.name, constraint),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// The metadata of a package needed to build the dependency index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourcePackage {
+    /// The package name
+    pub name: String,
+    /// The pkgbase, if it differs from the package name
+    pub base: Option<String>,
+    /// The version of the package
+    pub version: String,
+    /// The architecture of the package
+    pub arch: Option<String>,
+    /// The repository the package is in
+    pub repo: String,
+    /// Runtime dependencies
+    pub depends: Vec<Depend>,
+    /// Build dependencies
+    pub makedepends: Vec<Depend>,
+    /// Test dependencies
+    pub checkdepends: Vec<Depend>,
+    /// Names provided by the package
+    pub provides: Vec<Depend>,
+}
+
+impl SourcePackage {
+    /// Create a package of the given name and version, in no repository.
+    pub fn new(name: &str, version: &str) -> SourcePackage {
+        SourcePackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A source of packages to build the dependency index from, such as pacman's syncdbs.
+pub trait PackageSource {
+    /// Every package of every repository, in repository order.
+    fn packages(&self) -> &[SourcePackage];
+}
+
+/// Packages from the syncdbs of pacman's database.
+pub struct AlpmSource {
+    packages: Vec<SourcePackage>,
+}
+
+impl AlpmSource {
+    /// Read the syncdbs of the given repositories, in the root directory `root` or the default
+    /// ( / ) and the pacman database at `dbpath` or `var/lib/pacman` in the root directory.
+    pub fn new(root: Option<&str>, dbpath: Option<&str>, repos: &[String]) -> Result<AlpmSource> {
+        let pacman = alpm::Alpm::new(root.unwrap_or(ROOT_DIR), &db_path(root, dbpath))
+            .map_err(RebuildOrderError::PacmanDbInit)?;
+
+        for repo in repos {
+            let _repo = pacman.register_syncdb(repo.as_str(), SigLevel::DATABASE_OPTIONAL);
+        }
+
+        let mut packages = Vec::new();
+        for db in pacman.syncdbs() {
+            for pkg in db.pkgs() {
+                packages.push(from_alpm(db.name(), pkg));
+            }
+        }
+
+        Ok(AlpmSource { packages })
+    }
+}
+
+/// Convert an alpm package of the given repository.
+fn from_alpm(repo: &str, pkg: &alpm::Package) -> SourcePackage {
+    SourcePackage {
+        name: pkg.name().to_string(),
+        base: pkg.base().map(|x| x.to_string()),
+        version: pkg.version().to_string(),
+        arch: pkg.arch().map(|x| x.to_string()),
+        repo: repo.to_string(),
+        depends: pkg.depends().iter().map(Depend::from_alpm).collect(),
+        makedepends: pkg.makedepends().iter().map(Depend::from_alpm).collect(),
+        checkdepends: pkg.checkdepends().iter().map(Depend::from_alpm).collect(),
+        provides: pkg.provides().iter().map(Depend::from_alpm).collect(),
+    }
+}
+
+impl PackageSource for AlpmSource {
+    fn packages(&self) -> &[SourcePackage] {
+        &self.packages
+    }
+}
+
+/// Packages held in memory, in repository order.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    packages: Vec<SourcePackage>,
+}
+
+impl MemorySource {
+    /// Create a source of the given packages.
+    pub fn new(packages: Vec<SourcePackage>) -> MemorySource {
+        MemorySource { packages }
+    }
+}
+
+impl PackageSource for MemorySource {
+    fn packages(&self) -> &[SourcePackage] {
+        &self.packages
+    }
+}
+
+/// Packages of an upper source overlaid on top of a lower source.
+///
+/// A pkgbase of the upper source replaces every package of that pkgbase in the lower source, or
+/// with [`OverlaySource::per_package`] only the packages of the same name. Replacing packages keep
+/// the repository of the package they replace when they have none.
+pub struct OverlaySource {
+    packages: Vec<SourcePackage>,
+}
+
+impl OverlaySource {
+    /// Overlay the packages of `upper` on top of `lower`, replacing whole pkgbases.
+    pub fn new(lower: Box<dyn PackageSource>, upper: Box<dyn PackageSource>) -> OverlaySource {
+        OverlaySource::overlay(lower.as_ref(), upper.as_ref(), false)
+    }
+
+    /// Overlay the packages of `upper` on top of `lower`, replacing packages of the same name.
+    pub fn per_package(
+        lower: Box<dyn PackageSource>,
+        upper: Box<dyn PackageSource>,
+    ) -> OverlaySource {
+        OverlaySource::overlay(lower.as_ref(), upper.as_ref(), true)
+    }
+
+    /// Merge the packages of `upper` and the packages of `lower` they do not replace.
+    fn overlay(
+        lower: &dyn PackageSource,
+        upper: &dyn PackageSource,
+        per_package: bool,
+    ) -> OverlaySource {
+        let upper = upper.packages();
+        let replaced: HashSet<&str> = upper
+            .iter()
+            .map(|pkg| {
+                if per_package {
+                    &pkg.name
+                } else {
+                    pkg.base.as_deref().unwrap_or(&pkg.name)
+                }
+            })
+            .collect();
+
+        let mut packages = Vec::new();
+        let mut repos: HashMap<&str, &str> = HashMap::new();
+        for pkg in lower.packages() {
+            let pkgbase = pkg.base.as_deref().unwrap_or(&pkg.name);
+            let key = if per_package { &pkg.name } else { pkgbase };
+            if replaced.contains(key) {
+                repos.entry(pkgbase).or_insert(&pkg.repo);
+                repos.entry(&pkg.name).or_insert(&pkg.repo);
+            } else {
+                packages.push(pkg.clone());
+            }
+        }
+
+        for pkg in upper {
+            let mut pkg = pkg.clone();
+            if pkg.repo.is_empty() {
+                let pkgbase = pkg.base.as_deref().unwrap_or(&pkg.name);
+                if let Some(repo) = repos.get(pkg.name.as_str()).or_else(|| repos.get(pkgbase)) {
+                    pkg.repo = repo.to_string();
+                }
+            }
+            packages.push(pkg);
+        }
+
+        OverlaySource { packages }
+    }
+}
+
+impl PackageSource for OverlaySource {
+    fn packages(&self) -> &[SourcePackage] {
+        &self.packages
+    }
+}
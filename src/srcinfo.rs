@@ -0,0 +1,152 @@
+//! Reader for `.SRCINFO` files of a tree of package repositories, one directory per pkgbase.
+//!
+//! A `.SRCINFO` starts with the `pkgbase` section, followed by a `pkgname` section per split
+//! package which overrides keys of the `pkgbase` section, an empty value clears the key.
+//! Architecture specific keys such as `depends_x86_64` are added for the given architecture.
+
+use crate::error::RebuildOrderError;
+use crate::source::{Depend, PackageSource, SourcePackage};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Keys of a section, each with its values.
+type Section = HashMap<String, Vec<String>>;
+
+/// Packages read from the `.SRCINFO` files of a directory tree.
+#[derive(Debug, Clone, Default)]
+pub struct SrcinfoSource {
+    packages: Vec<SourcePackage>,
+}
+
+impl SrcinfoSource {
+    /// Read the `.SRCINFO` of every directory in `dir`, sorted by directory name, leaving out
+    /// packages not built for `arch`. Directories without a `.SRCINFO` are skipped.
+    pub fn open(dir: &Path, arch: &str) -> Result<SrcinfoSource> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .map_err(|e| RebuildOrderError::read(".SRCINFO", dir, e))?
+            .filter_map(|entry| Some(entry.ok()?.path().join(".SRCINFO")))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+
+        let mut packages = Vec::new();
+        for path in paths {
+            let srcinfo = fs::read_to_string(&path)
+                .map_err(|e| RebuildOrderError::read(".SRCINFO", &path, e))?;
+            packages.extend(
+                parse_srcinfo(&srcinfo, arch)
+                    .map_err(|e| RebuildOrderError::read(".SRCINFO", &path, e))?,
+            );
+        }
+
+        Ok(SrcinfoSource { packages })
+    }
+}
+
+impl PackageSource for SrcinfoSource {
+    fn packages(&self) -> &[SourcePackage] {
+        &self.packages
+    }
+}
+
+/// The values of `key` in the package section, or the pkgbase section when not overridden.
+fn values<'a>(pkgbase: &'a Section, section: &'a Section, key: &str) -> Vec<&'a str> {
+    section
+        .get(key)
+        .or_else(|| pkgbase.get(key))
+        .into_iter()
+        .flatten()
+        .filter(|x| !x.is_empty())
+        .map(|x| x.as_str())
+        .collect()
+}
+
+/// The dependencies of `key` and its architecture specific `key_arch`.
+fn depends(pkgbase: &Section, section: &Section, key: &str, arch: &str) -> Vec<Depend> {
+    let arch_key = format!("{key}_{arch}");
+    values(pkgbase, section, key)
+        .into_iter()
+        .chain(values(pkgbase, section, &arch_key))
+        .map(Depend::parse)
+        .collect()
+}
+
+/// Parse a `.SRCINFO` into its split packages built for `arch`.
+pub fn parse_srcinfo(srcinfo: &str, arch: &str) -> Result<Vec<SourcePackage>, String> {
+    let mut pkgbase: Option<(String, Section)> = None;
+    let mut pkgnames: Vec<(String, Section)> = Vec::new();
+
+    for line in srcinfo.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("invalid line '{line}'"));
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        let section = match key {
+            "pkgbase" if pkgbase.is_none() => {
+                pkgbase = Some((value.to_string(), Section::new()));
+                continue;
+            }
+            "pkgname" if pkgbase.is_some() => {
+                pkgnames.push((value.to_string(), Section::new()));
+                continue;
+            }
+            "pkgbase" => return Err("multiple pkgbase".to_string()),
+            _ => match pkgnames.last_mut().or(pkgbase.as_mut()) {
+                Some((_, section)) => section,
+                None => return Err(format!("'{key}' before pkgbase")),
+            },
+        };
+        section
+            .entry(key.to_string())
+            .or_default()
+            .push(value.to_string());
+    }
+
+    let Some((base, fields)) = pkgbase else {
+        return Err("missing pkgbase".to_string());
+    };
+    // the pkgbase section is passed as package section as well for keys only it can set
+    let field = |key: &str| values(&fields, &fields, key).first().copied();
+    let (Some(pkgver), Some(pkgrel)) = (field("pkgver"), field("pkgrel")) else {
+        return Err("missing pkgver or pkgrel".to_string());
+    };
+    let version = match field("epoch") {
+        Some(epoch) => format!("{epoch}:{pkgver}-{pkgrel}"),
+        None => format!("{pkgver}-{pkgrel}"),
+    };
+
+    let makedepends = depends(&fields, &fields, "makedepends", arch);
+    let checkdepends = depends(&fields, &fields, "checkdepends", arch);
+
+    let mut packages = Vec::new();
+    for (name, section) in &pkgnames {
+        let archs = values(&fields, section, "arch");
+        let pkg_arch = if archs.contains(&"any") {
+            "any"
+        } else if archs.contains(&arch) {
+            arch
+        } else {
+            continue;
+        };
+
+        packages.push(SourcePackage {
+            name: name.clone(),
+            base: Some(base.clone()),
+            version: version.clone(),
+            arch: Some(pkg_arch.to_string()),
+            repo: String::new(),
+            depends: depends(&fields, section, "depends", arch),
+            makedepends: makedepends.clone(),
+            checkdepends: checkdepends.clone(),
+            provides: depends(&fields, section, "provides", arch),
+        });
+    }
+
+    Ok(packages)
+}
@@ -0,0 +1,36 @@
+use crate::cycles::BuildGraph;
+use petgraph::algo::toposort;
+use petgraph::visit::EdgeRef;
+
+/// Group the acyclic build graph into stages, every package only depends on packages in earlier
+/// stages so the packages of a stage can be built in parallel.
+///
+/// Returns the package names of every stage, sorted by name, and whether it is a bootstrap build.
+pub fn build_stages(build_graph: &BuildGraph) -> Vec<Vec<(&str, bool)>> {
+    let graph = &build_graph.graph;
+    let order = toposort(graph, None).expect("build graph has no dependency cycles");
+
+    // The stage of a package is one after the latest stage of its dependencies.
+    let mut stage_of = vec![0; graph.node_count()];
+    for &nx in &order {
+        for edge in graph.edges(nx) {
+            let target = edge.target().index();
+            stage_of[target] = stage_of[target].max(stage_of[nx.index()] + 1);
+        }
+    }
+
+    let mut stages: Vec<Vec<(&str, bool)>> = Vec::new();
+    for nx in order {
+        let stage = stage_of[nx.index()];
+        if stages.len() <= stage {
+            stages.resize_with(stage + 1, Vec::new);
+        }
+        stages[stage].push((graph[nx].as_str(), build_graph.bootstrap.contains(&nx)));
+    }
+
+    for stage in &mut stages {
+        stage.sort();
+    }
+
+    stages
+}
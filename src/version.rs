@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// The comparison of a versioned dependency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Comparison {
+    /// Exactly the version (=)
+    Eq,
+    /// The version or newer (>=)
+    Ge,
+    /// The version or older (<=)
+    Le,
+    /// Newer than the version (>)
+    Gt,
+    /// Older than the version (<)
+    Lt,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Comparison::Eq => write!(f, "="),
+            Comparison::Ge => write!(f, ">="),
+            Comparison::Le => write!(f, "<="),
+            Comparison::Gt => write!(f, ">"),
+            Comparison::Lt => write!(f, "<"),
+        }
+    }
+}
+
+/// A version constraint of a dependency, such as `>=2`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Constraint {
+    /// How the version is compared
+    pub comparison: Comparison,
+    /// The version to compare with
+    pub version: String,
+}
+
+impl Constraint {
+    /// Retrieve the version constraint of an alpm dependency, if it has one.
+    pub fn from_dep(dep: &alpm::Dep) -> Option<Constraint> {
+        let comparison = match dep.depmod() {
+            alpm::DepMod::Any => return None,
+            alpm::DepMod::Eq => Comparison::Eq,
+            alpm::DepMod::Ge => Comparison::Ge,
+            alpm::DepMod::Le => Comparison::Le,
+            alpm::DepMod::Gt => Comparison::Gt,
+            alpm::DepMod::Lt => Comparison::Lt,
+        };
+
+        Some(Constraint {
+            comparison,
+            version: dep.version()?.to_string(),
+        })
+    }
+
+    /// Whether the given version satisfies the constraint, compared using pacman's vercmp.
+    pub fn satisfied_by(&self, version: &str) -> bool {
+        let ordering = alpm::vercmp(version, self.version.as_str());
+        match self.comparison {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ge => ordering != Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Lt => ordering == Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.comparison, self.version)
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{find_node, Dependency, PackageInfo, RebuildGraph};
+use petgraph::algo::all_simple_paths;
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// A dependency chain from a provided package to a package in the rebuild graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    /// The provided package the chain starts at
+    pub root: String,
+    /// Every hop of the chain as the reverse dependency and its dependency on the previous package,
+    /// or as the dependency of the previous package when ordering dependencies, without a
+    /// dependency for a split package of the previous package
+    pub hops: Vec<(String, Option<Dependency>)>,
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        for (rev_dep, dependency) in &self.hops {
+            match dependency {
+                Some(dependency) => write!(f, " -> {rev_dep} ({dependency})")?,
+                None => write!(f, " -> {rev_dep} (split package)")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The rebuild graph with hops between the split packages of a pkgbase.
+type ChainGraph<'a> = DiGraph<&'a str, Option<&'a Dependency>>;
+
+/// Return the rebuild graph with a hop between every two split packages of the same pkgbase,
+/// which are built together and have no edges between them. The node indices stay the same.
+fn chain_graph<'a>(
+    graph: &'a RebuildGraph,
+    packages: &HashMap<String, PackageInfo>,
+) -> ChainGraph<'a> {
+    let mut split_packages: HashMap<&str, Vec<NodeIndex>> = HashMap::new();
+    for nx in graph.node_indices() {
+        if let Some(info) = packages.get(&graph[nx]) {
+            split_packages.entry(&info.pkgbase).or_default().push(nx);
+        }
+    }
+
+    let mut chain_graph = graph.map(|_, pkg| pkg.as_str(), |_, dependency| Some(dependency));
+    for siblings in split_packages.values() {
+        for &from in siblings {
+            for &to in siblings.iter().filter(|&&to| to != from) {
+                chain_graph.add_edge(from, to, None);
+            }
+        }
+    }
+
+    chain_graph
+}
+
+/// Build the chain following the given path of nodes.
+fn chain(graph: &ChainGraph, path: &[NodeIndex]) -> Chain {
+    let hops = path
+        .windows(2)
+        .map(|pair| {
+            let edge = graph
+                .find_edge(pair[0], pair[1])
+                .expect("path follows edges of the graph");
+            (graph[pair[1]].to_string(), graph[edge].cloned())
+        })
+        .collect();
+
+    Chain {
+        root: graph[path[0]].to_string(),
+        hops,
+    }
+}
+
+/// Find the shortest dependency chain from the provided package(s) to the target, preferring
+/// earlier provided packages for chains of equal length. Split packages of a package in the chain,
+/// as found in `packages`, are a hop of their own.
+///
+/// Returns `None` when the target is not reachable from the provided package(s).
+pub fn shortest_chain(
+    graph: &RebuildGraph,
+    pkgnames: &[String],
+    packages: &HashMap<String, PackageInfo>,
+    target: &str,
+) -> Option<Chain> {
+    let target = find_node(graph, target)?;
+    let roots: Vec<NodeIndex> = pkgnames
+        .iter()
+        .filter_map(|pkg| find_node(graph, pkg))
+        .collect();
+    let graph = chain_graph(graph, packages);
+
+    let mut to_visit = VecDeque::new();
+    let mut previous: HashMap<NodeIndex, Option<NodeIndex>> = HashMap::new();
+    for nx in roots {
+        if previous.insert(nx, None).is_none() {
+            to_visit.push_back(nx);
+        }
+    }
+
+    while let Some(nx) = to_visit.pop_front() {
+        if nx == target {
+            let mut path = vec![nx];
+            while let Some(&Some(prev)) = previous.get(path.last().unwrap()) {
+                path.push(prev);
+            }
+            path.reverse();
+            return Some(chain(&graph, &path));
+        }
+
+        let mut edges: Vec<_> = graph.edges(nx).collect();
+        edges.sort_by(|a, b| graph[a.target()].cmp(graph[b.target()]));
+        for edge in edges {
+            if let Entry::Vacant(entry) = previous.entry(edge.target()) {
+                entry.insert(Some(nx));
+                to_visit.push_back(edge.target());
+            }
+        }
+    }
+
+    None
+}
+
+/// Find every dependency chain without repeated packages from the provided package(s) to the
+/// target, sorted by length. Split packages of a package in the chain, as found in `packages`, are
+/// a hop of their own.
+///
+/// The number of chains grows quickly with the size of the graph.
+pub fn all_chains(
+    graph: &RebuildGraph,
+    pkgnames: &[String],
+    packages: &HashMap<String, PackageInfo>,
+    target: &str,
+) -> Vec<Chain> {
+    let Some(target) = find_node(graph, target) else {
+        return Vec::new();
+    };
+    let roots: Vec<NodeIndex> = pkgnames
+        .iter()
+        .filter_map(|pkg| find_node(graph, pkg))
+        .collect();
+    let graph = chain_graph(graph, packages);
+
+    let mut chains = Vec::new();
+    for root in roots {
+        if root == target {
+            chains.push(chain(&graph, &[root]));
+            continue;
+        }
+        for path in all_simple_paths::<Vec<_>, _>(&graph, root, target, 0, None) {
+            chains.push(chain(&graph, &path));
+        }
+    }
+
+    chains.sort_by_cached_key(|chain| (chain.hops.len(), chain.to_string()));
+    chains.dedup();
+    chains
+}
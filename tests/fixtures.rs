@@ -1,3 +1,5 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use rstest::fixture;
 use std::convert::TryFrom;
 use std::fmt;
@@ -6,6 +8,7 @@ use std::fs::File;
 use std::io::Write;
 use tar::{Builder, Header};
 use tempfile::{tempdir, TempDir};
+use xz2::write::XzEncoder;
 
 // pacman database version (lib/libalpm/be_local.c)
 const ALPM_DB_VERSION: &str = "9";
@@ -42,6 +45,7 @@ impl Package {
         }
     }
 
+    #[allow(clippy::single_char_add_str)]
     fn desc(&self) -> String {
         let mut desc = String::from("");
 
@@ -51,6 +55,9 @@ impl Package {
         let base = format!("%BASE%\n{}\n", self.base);
         desc.push_str(&base);
 
+        let version = format!("%VERSION%\n{}\n", self.version);
+        desc.push_str(&version);
+
         if !self.depends.is_empty() {
             desc.push_str("%DEPENDS%\n");
             for dep in self.depends.iter() {
@@ -141,6 +148,13 @@ fn init_repodb(reponame: String, packages: Vec<Package>) -> (TempDir, String) {
 }
 
 fn create_db(dbloc: String, pkgs: Vec<Package>) {
+    let data = db_archive(pkgs);
+
+    let mut afile = File::create(dbloc).unwrap();
+    afile.write_all(&data).unwrap();
+}
+
+fn db_archive(pkgs: Vec<Package>) -> Vec<u8> {
     let mut archive = Builder::new(Vec::new());
 
     for pkg in pkgs {
@@ -151,10 +165,7 @@ fn create_db(dbloc: String, pkgs: Vec<Package>) {
     }
 
     archive.finish().unwrap();
-    let data = archive.into_inner().unwrap();
-
-    let mut afile = File::create(dbloc).unwrap();
-    afile.write_all(&data).unwrap();
+    archive.into_inner().unwrap()
 }
 
 #[fixture]
@@ -399,6 +410,62 @@ pub fn dependency_cycle() -> (Vec<Package>, Option<String>, Vec<String>, TempDir
     (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
 }
 
+#[fixture]
+pub fn check_depends_cycle() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![String::from("testpkg2")],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec![],
+        vec![],
+        vec![],
+        vec![testpkg.name.clone()],
+    );
+    let packages = vec![testpkg, testpkg2];
+
+    let reponame = "test";
+    let (tempdir, dbpath) = init_repodb(reponame.to_string(), packages.clone());
+
+    (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
+}
+
+#[fixture]
+pub fn make_depends_cycle() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![String::from("testpkg2")],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec![],
+        vec![testpkg.name.clone()],
+        vec![],
+        vec![],
+    );
+    let packages = vec![testpkg, testpkg2];
+
+    let reponame = "test";
+    let (tempdir, dbpath) = init_repodb(reponame.to_string(), packages.clone());
+
+    (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
+}
+
 #[fixture]
 pub fn multiple_pkgnames() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
     let testpkg = Package::new(
@@ -445,3 +512,516 @@ pub fn multiple_pkgnames() -> (Vec<Package>, Option<String>, Vec<String>, TempDi
 
     (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
 }
+
+#[fixture]
+pub fn split_packages() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg0",
+        "testpkg0",
+        "1-1",
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg1 = Package::new(
+        "testpkg1",
+        "testbase",
+        "1-1",
+        vec![testpkg.name.clone()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg1_libs = Package::new(
+        "testpkg1-libs",
+        "testbase",
+        "1-1",
+        vec![testpkg.name.clone()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec![testpkg1_libs.name.clone()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let packages = vec![testpkg, testpkg1, testpkg1_libs, testpkg2];
+
+    let reponame = "test";
+    let (tempdir, dbpath) = init_repodb(reponame.to_string(), packages.clone());
+
+    (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
+}
+
+#[fixture]
+pub fn split_package_make_depends() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg1",
+        "testbase",
+        "1-1",
+        vec![],
+        vec![String::from("testpkg2")],
+        vec![],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testbase",
+        "1-1",
+        vec![testpkg.name.clone()],
+        vec![String::from("testpkg2")],
+        vec![],
+        vec![],
+    );
+    let packages = vec![testpkg, testpkg2];
+
+    let reponame = "test";
+    let (tempdir, dbpath) = init_repodb(reponame.to_string(), packages.clone());
+
+    (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
+}
+
+#[fixture]
+pub fn soname_depends() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![],
+        vec![],
+        vec!["libtest.so=1-64".to_string()],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec!["libtest.so=1-64".to_string()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg3 = Package::new(
+        "testpkg3",
+        "testpkg3",
+        "1-1",
+        vec![testpkg.name.clone()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let packages = vec![testpkg, testpkg2, testpkg3];
+
+    let reponame = "test";
+    let (tempdir, dbpath) = init_repodb(reponame.to_string(), packages.clone());
+
+    (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
+}
+
+#[fixture]
+pub fn provides_depth() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec![testpkg.name.clone()],
+        vec![],
+        vec!["virtual".to_string()],
+        vec![],
+    );
+    let testpkg3 = Package::new(
+        "testpkg3",
+        "testpkg3",
+        "1-1",
+        vec![testpkg2.provides[0].clone()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg4 = Package::new(
+        "testpkg4",
+        "testpkg4",
+        "1-1",
+        vec![],
+        vec![],
+        vec![testpkg2.provides[0].clone()],
+        vec![],
+    );
+    let packages = vec![testpkg, testpkg2, testpkg3, testpkg4];
+
+    let reponame = "test";
+    let (tempdir, dbpath) = init_repodb(reponame.to_string(), packages.clone());
+
+    (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
+}
+
+#[fixture]
+pub fn versioned_depends() -> (Vec<Package>, Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![],
+        vec![],
+        vec!["virtual=1".to_string()],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec!["testpkg1<2".to_string()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg3 = Package::new(
+        "testpkg3",
+        "testpkg3",
+        "1-1",
+        vec!["testpkg1>=2".to_string()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg4 = Package::new(
+        "testpkg4",
+        "testpkg4",
+        "1-1",
+        vec!["virtual>=2".to_string()],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let packages = vec![testpkg, testpkg2, testpkg3, testpkg4];
+
+    let reponame = "test";
+    let (tempdir, dbpath) = init_repodb(reponame.to_string(), packages.clone());
+
+    (packages, Some(dbpath), vec![reponame.to_string()], tempdir)
+}
+
+#[fixture]
+pub fn db_files() -> (Vec<(String, String)>, TempDir) {
+    let testpkg1 = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec![String::from("testpkg1")],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg3 = Package::new(
+        "testpkg3",
+        "testpkg3",
+        "1-1",
+        vec![],
+        vec![String::from("testpkg2")],
+        vec![],
+        vec![],
+    );
+    let testpkg4 = Package::new(
+        "testpkg4",
+        "testpkg4",
+        "1-1",
+        vec![String::from("testpkg3")],
+        vec![],
+        vec![],
+        vec![],
+    );
+
+    let tempdir = tempdir().unwrap();
+    let mut db_files = Vec::new();
+
+    // uncompressed, gzip, zstd and xz compressed repository databases
+    let tar = db_archive(vec![testpkg1]);
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(&db_archive(vec![testpkg2])).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let zstd = zstd::encode_all(db_archive(vec![testpkg3]).as_slice(), 0).unwrap();
+    let mut xz = XzEncoder::new(Vec::new(), 6);
+    xz.write_all(&db_archive(vec![testpkg4])).unwrap();
+    let xz = xz.finish().unwrap();
+
+    for (repo, data) in [
+        ("core", tar),
+        ("extra", gzip),
+        ("multilib", zstd),
+        ("testing", xz),
+    ] {
+        let path = tempdir.path().join(format!("{}.db", repo));
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        db_files.push((repo.to_string(), path.display().to_string()));
+    }
+
+    (db_files, tempdir)
+}
+
+fn write_srcinfo(dir: &std::path::Path, pkgbase: &str, srcinfo: &str) {
+    let pkgdir = dir.join(pkgbase);
+    fs::create_dir(&pkgdir).unwrap();
+    fs::write(pkgdir.join(".SRCINFO"), srcinfo).unwrap();
+}
+
+#[fixture]
+pub fn srcinfo_tree() -> TempDir {
+    let tempdir = tempdir().unwrap();
+
+    write_srcinfo(
+        tempdir.path(),
+        "testpkg1",
+        "pkgbase = testpkg1
+\tpkgver = 2
+\tpkgrel = 1
+\tepoch = 1
+\tarch = x86_64
+\tarch = aarch64
+
+pkgname = testpkg1
+
+pkgname = testpkg1-libs
+\tprovides = libtest.so=2-64
+",
+    );
+    write_srcinfo(
+        tempdir.path(),
+        "testpkg2",
+        "pkgbase = testpkg2
+\tpkgver = 1
+\tpkgrel = 1
+\tarch = x86_64
+\tarch = aarch64
+\tdepends_x86_64 = libtest.so=2-64
+\tdepends_aarch64 = testpkg1
+
+pkgname = testpkg2
+",
+    );
+    write_srcinfo(
+        tempdir.path(),
+        "testpkg3",
+        "pkgbase = testpkg3
+\tpkgver = 1
+\tpkgrel = 1
+\tarch = any
+\tmakedepends = testpkg2
+\tdepends = testpkg1
+
+pkgname = testpkg3
+
+pkgname = testpkg3-docs
+\tdepends =
+",
+    );
+    write_srcinfo(
+        tempdir.path(),
+        "testpkg4",
+        "pkgbase = testpkg4
+\tpkgver = 1
+\tpkgrel = 1
+\tarch = aarch64
+\tdepends = testpkg3
+
+pkgname = testpkg4
+",
+    );
+
+    tempdir
+}
+
+#[fixture]
+pub fn srcinfo_overlay() -> TempDir {
+    let tempdir = tempdir().unwrap();
+
+    write_srcinfo(
+        tempdir.path(),
+        "testpkg3",
+        "pkgbase = testpkg3
+\tpkgver = 2
+\tpkgrel = 1
+\tarch = x86_64
+\tmakedepends = testpkg1
+
+pkgname = testpkg3
+",
+    );
+
+    tempdir
+}
+
+fn write_package(dir: &std::path::Path, filename: &str, pkginfo: &str) {
+    let mut archive = Builder::new(Vec::new());
+    for (path, data) in [(".BUILDINFO", "format = 2\n"), (".PKGINFO", pkginfo)] {
+        let mut header = Header::new_gnu();
+        header.set_path(path).unwrap();
+        header.set_size(u64::try_from(data.len()).unwrap());
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive.append(&header, data.as_bytes()).unwrap();
+    }
+    archive.finish().unwrap();
+
+    let data = zstd::encode_all(archive.into_inner().unwrap().as_slice(), 0).unwrap();
+    fs::write(dir.join(filename), data).unwrap();
+}
+
+#[fixture]
+pub fn built_packages() -> TempDir {
+    let tempdir = tempdir().unwrap();
+
+    write_package(
+        tempdir.path(),
+        "testpkg3-2-1-x86_64.pkg.tar.zst",
+        "# Generated by makepkg
+pkgname = testpkg3
+pkgbase = testpkg3
+pkgver = 2-1
+arch = x86_64
+depend = testpkg1
+",
+    );
+    write_package(
+        tempdir.path(),
+        "testpkg3-1.5-1-x86_64.pkg.tar.zst",
+        "pkgname = testpkg3
+pkgbase = testpkg3
+pkgver = 1.5-1
+arch = x86_64
+depend = testpkg2
+",
+    );
+    fs::write(
+        tempdir.path().join("testpkg3-2-1-x86_64.pkg.tar.zst.sig"),
+        "signature",
+    )
+    .unwrap();
+
+    tempdir
+}
+
+#[fixture]
+pub fn testing_repo() -> (Option<String>, TempDir) {
+    let testpkg1 = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "1-1",
+        vec![String::from("testpkg1")],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testing_testpkg2 = Package::new(
+        "testpkg2",
+        "testpkg2",
+        "2-1",
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let testpkg3 = Package::new(
+        "testpkg3",
+        "testpkg3",
+        "1-1",
+        vec![String::from("testpkg1")],
+        vec![],
+        vec![],
+        vec![],
+    );
+
+    let (tempdir, dbpath) = init_repodb("core".to_string(), vec![testpkg1, testpkg2]);
+    let dbloc = tempdir.path().join("sync").join("core-testing.db");
+    create_db(
+        dbloc.display().to_string(),
+        vec![testing_testpkg2, testpkg3],
+    );
+
+    (Some(dbpath), tempdir)
+}
+
+#[fixture]
+pub fn repo_priority() -> (Option<String>, Vec<String>, TempDir) {
+    let testpkg = Package::new(
+        "testpkg1",
+        "testpkg1",
+        "1-1",
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    );
+    let mut core = vec![testpkg];
+    for name in ["testpkg5", "testpkg3", "testpkg4", "testpkg2"] {
+        core.push(Package::new(
+            name,
+            name,
+            "1-1",
+            vec![String::from("testpkg1")],
+            vec![],
+            vec![],
+            vec![],
+        ));
+    }
+    core.push(Package::new(
+        "testpkg6",
+        "testpkg6",
+        "1-1",
+        vec![String::from("testpkg0"), String::from("testpkg2")],
+        vec![],
+        vec![],
+        vec![],
+    ));
+    let extra = vec![Package::new(
+        "testpkg0",
+        "testpkg0",
+        "1-1",
+        vec![String::from("testpkg1")],
+        vec![],
+        vec![],
+        vec![],
+    )];
+
+    let (tempdir, dbpath) = init_repodb("core".to_string(), core);
+    let dbloc = tempdir.path().join("sync").join("extra.db");
+    create_db(dbloc.display().to_string(), extra);
+
+    (
+        Some(dbpath),
+        vec!["core".to_string(), "extra".to_string()],
+        tempdir,
+    )
+}
@@ -1,12 +1,29 @@
+use arch_rebuild_order::cache;
+use arch_rebuild_order::config::PacmanConf;
+use arch_rebuild_order::cycles::find_cycles;
+use arch_rebuild_order::index::{DependencyIndex, DuplicatePolicy};
+use arch_rebuild_order::json;
+use arch_rebuild_order::plan::Reason;
+use arch_rebuild_order::repodb;
+use arch_rebuild_order::source::{Depend, MemorySource, SourcePackage};
+use arch_rebuild_order::srcinfo::SrcinfoSource;
+use arch_rebuild_order::why::{all_chains, shortest_chain};
+use arch_rebuild_order::{DependKind, Dependency, RebuildOrderOptions};
 use rstest::rstest;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
 use tempfile::TempDir;
 
 pub mod fixtures;
 
 use fixtures::{
-    dependency_cycle, dependency_depth, invalid_dbpath, multiple_deps, multiple_pkgnames,
-    no_reverse_deps, provides_make_depends, reverse_check_deps, reverse_deps, reverse_make_deps,
-    Package,
+    built_packages, check_depends_cycle, db_files, dependency_cycle, dependency_depth,
+    invalid_dbpath, make_depends_cycle, multiple_deps, multiple_pkgnames, no_reverse_deps,
+    provides_depth, provides_make_depends, repo_priority, reverse_check_deps, reverse_deps,
+    reverse_make_deps, soname_depends, split_package_make_depends, split_packages, srcinfo_overlay,
+    srcinfo_tree, testing_repo, versioned_depends, Package,
 };
 
 #[rstest]
@@ -14,7 +31,12 @@ use fixtures::{
 fn test_invalid_dbpath(invalid_dbpath: (Vec<String>, Option<String>)) {
     let pkgnames = invalid_dbpath.0;
     let dbpath = invalid_dbpath.1;
-    arch_rebuild_order::run(pkgnames, dbpath, vec![], None, false, false).unwrap();
+    arch_rebuild_order::run(
+        &RebuildOrderOptions::new(pkgnames)
+            .dbpath(dbpath)
+            .repos(vec![]),
+    )
+    .unwrap();
 }
 
 /// A package without any reverse dependencies should only print the given package
@@ -23,14 +45,12 @@ fn test_no_reverse_deps(no_reverse_deps: (Vec<Package>, Option<String>, Vec<Stri
     let packages = no_reverse_deps.0;
 
     let res = arch_rebuild_order::run(
-        vec![packages[0].name.clone()],
-        no_reverse_deps.1,
-        no_reverse_deps.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![packages[0].name.clone()])
+            .dbpath(no_reverse_deps.1)
+            .repos(no_reverse_deps.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     assert_eq!(packages[0], res.trim());
 }
 
@@ -42,14 +62,12 @@ fn test_reverse_deps(reverse_deps: (Vec<String>, Option<String>, Vec<String>, Te
     let pkgname = &pkgnames[0];
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        reverse_deps.1,
-        reverse_deps.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(reverse_deps.1)
+            .repos(reverse_deps.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     assert_eq!(pkgnames, res_pkgs);
 }
@@ -62,14 +80,12 @@ fn test_reverse_make_deps(reverse_make_deps: (Vec<Package>, Option<String>, Vec<
     let pkgname = &packages[0].name;
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        reverse_make_deps.1,
-        reverse_make_deps.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(reverse_make_deps.1)
+            .repos(reverse_make_deps.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     assert_eq!(packages, res_pkgs);
 }
@@ -84,14 +100,12 @@ fn test_reverse_check_deps_default(
     let pkgname = &packages[0].name;
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        reverse_check_deps.1,
-        reverse_check_deps.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(reverse_check_deps.1)
+            .repos(reverse_check_deps.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     assert_eq!(vec![pkgname.to_string()], res_pkgs);
 }
@@ -106,14 +120,13 @@ fn test_reverse_check_deps(
     let pkgname = &packages[0].name;
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        reverse_check_deps.1,
-        reverse_check_deps.2,
-        None,
-        false,
-        true,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(reverse_check_deps.1)
+            .repos(reverse_check_deps.2)
+            .with_check_depends(true),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     assert_eq!(packages, res_pkgs);
 }
@@ -128,14 +141,12 @@ fn test_provides_make_depends(
     let pkgname = &packages[0].name;
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        provides_make_depends.1,
-        provides_make_depends.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(provides_make_depends.1)
+            .repos(provides_make_depends.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     assert_eq!(packages, res_pkgs);
 }
@@ -148,14 +159,12 @@ fn test_multiple_deps(multiple_deps: (Vec<Package>, Option<String>, Vec<String>,
     let pkgname = &packages[0];
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        multiple_deps.1,
-        multiple_deps.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(multiple_deps.1)
+            .repos(multiple_deps.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     assert_eq!(packages[0], res_pkgs[0]);
 }
@@ -168,43 +177,131 @@ fn test_dependency_depth(dependency_depth: (Vec<Package>, Option<String>, Vec<St
     let pkgname = &packages[0];
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        dependency_depth.1,
-        dependency_depth.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(dependency_depth.1)
+            .repos(dependency_depth.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     assert_eq!(packages[0], res_pkgs[0]);
 }
 
 /// Given a package 'testpkg1' with a dependency on 'testpkg2' and 'testpkg2' having a dependency
-/// on 'testpkg1'. Providing 'testpkg1' should return 'testpkg1 testpkg2'
-/// TODO: There should be a warning that there is a dependency cycle and the dep cycle should be
-/// broken #3.
+/// on 'testpkg1'. Providing 'testpkg1' should return 'testpkg1:bootstrap testpkg2 testpkg1' as
+/// the cycle can only be broken by building 'testpkg1' twice.
 #[rstest]
 fn test_dependency_cycle(dependency_cycle: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
     let packages = dependency_cycle.0;
     let pkgname = &packages[0];
 
     let res = arch_rebuild_order::run(
-        vec![pkgname.to_string()],
-        dependency_cycle.1,
-        dependency_cycle.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(dependency_cycle.1)
+            .repos(dependency_cycle.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
-    assert_eq!(packages[0], res_pkgs[0]);
+    assert_eq!(res_pkgs, vec!["testpkg1:bootstrap", "testpkg2", "testpkg1"]);
+}
+
+/// Given a package 'testpkg1' with a dependency on 'testpkg2' and 'testpkg2' having a check
+/// dependency on 'testpkg1'. Providing 'testpkg1' with check dependencies should return
+/// 'testpkg2 testpkg1' as the check dependency is dropped to break the cycle.
+#[rstest]
+fn test_check_depends_cycle(
+    check_depends_cycle: (Vec<Package>, Option<String>, Vec<String>, TempDir),
+) {
+    let packages = check_depends_cycle.0;
+    let pkgname = &packages[0];
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(check_depends_cycle.1)
+            .repos(check_depends_cycle.2)
+            .with_check_depends(true),
+    )
+    .unwrap()
+    .to_string();
+    let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
+    assert_eq!(res_pkgs, vec!["testpkg2", "testpkg1"]);
+}
+
+/// Given a package 'testpkg1' with a dependency on 'testpkg2' and 'testpkg2' having a make
+/// dependency on 'testpkg1'. Providing 'testpkg1' should return 'testpkg2 testpkg1' as the make
+/// dependency is dropped to break the cycle.
+#[rstest]
+fn test_make_depends_cycle(
+    make_depends_cycle: (Vec<Package>, Option<String>, Vec<String>, TempDir),
+) {
+    let packages = make_depends_cycle.0;
+    let pkgname = &packages[0];
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(make_depends_cycle.1)
+            .repos(make_depends_cycle.2),
+    )
+    .unwrap()
+    .to_string();
+    let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
+    assert_eq!(res_pkgs, vec!["testpkg2", "testpkg1"]);
+}
+
+/// Given a package 'testpkg1' with a dependency on 'testpkg2' and 'testpkg2' having a dependency
+/// on 'testpkg1'. The cycle should be reported with both packages and their dependencies.
+#[rstest]
+fn test_find_cycles(dependency_cycle: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let packages = dependency_cycle.0;
+    let pkgname = &packages[0];
+
+    let graph = arch_rebuild_order::rebuild_graph(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(dependency_cycle.1)
+            .repos(dependency_cycle.2),
+    )
+    .unwrap()
+    .graph;
+    let cycles = find_cycles(&graph);
+    assert_eq!(cycles.len(), 1);
+    assert_eq!(cycles[0].packages, vec!["testpkg1", "testpkg2"]);
+    assert_eq!(
+        cycles[0].edges,
+        vec![
+            (
+                "testpkg1".to_string(),
+                "testpkg2".to_string(),
+                DependKind::Depends
+            ),
+            (
+                "testpkg2".to_string(),
+                "testpkg1".to_string(),
+                DependKind::Depends
+            ),
+        ]
+    );
+}
+
+/// A dependency chain without cycles should not report any cycle
+#[rstest]
+fn test_no_cycles(dependency_depth: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let packages = dependency_depth.0;
+    let pkgname = &packages[0];
+
+    let graph = arch_rebuild_order::rebuild_graph(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(dependency_depth.1)
+            .repos(dependency_depth.2),
+    )
+    .unwrap()
+    .graph;
+    assert!(find_cycles(&graph).is_empty());
 }
 
 /// Given two packages names as input, with testpkg1 being a reverse dependency for testpkg2 and
 /// testpkg3 and testpkg4 being a dependency of testpkg2. Providing "testpkg1 testpkg2" should
-/// return "testpkg1 testpkg3 testpkg2 testpkg4"
+/// return "testpkg1 testpkg2 testpkg3 testpkg4"
 #[rstest]
 fn test_multiple_pkgnames(multiple_pkgnames: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
     let packages = multiple_pkgnames.0;
@@ -212,19 +309,43 @@ fn test_multiple_pkgnames(multiple_pkgnames: (Vec<Package>, Option<String>, Vec<
     let pkgname2 = &packages[1];
 
     let res = arch_rebuild_order::run(
-        vec![pkgname1.to_string(), pkgname2.to_string()],
-        multiple_pkgnames.1,
-        multiple_pkgnames.2,
-        None,
-        false,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname1.to_string(), pkgname2.to_string()])
+            .dbpath(multiple_pkgnames.1)
+            .repos(multiple_pkgnames.2),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
-    let expected = vec!["testpkg1", "testpkg3", "testpkg2", "testpkg4"];
+    let expected = vec!["testpkg1", "testpkg2", "testpkg3", "testpkg4"];
     assert_eq!(res_pkgs, expected);
 }
 
+/// Given two packages names as input, with testpkg1 being a reverse dependency for testpkg2 and
+/// testpkg3 and testpkg4 being a dependency of testpkg2. Providing "testpkg1 testpkg2" with stages
+/// should return "testpkg1" in the first stage, "testpkg2 testpkg3" in the second and "testpkg4" in
+/// the third stage.
+#[rstest]
+fn test_stages(multiple_pkgnames: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let packages = multiple_pkgnames.0;
+    let pkgname1 = &packages[0];
+    let pkgname2 = &packages[1];
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec![pkgname1.to_string(), pkgname2.to_string()])
+            .dbpath(multiple_pkgnames.1)
+            .repos(multiple_pkgnames.2),
+    )
+    .unwrap()
+    .format_stages();
+    let res_stages: Vec<&str> = res.trim().lines().collect();
+    let expected = vec![
+        "stage 1: testpkg1",
+        "stage 2: testpkg2 testpkg3",
+        "stage 3: testpkg4",
+    ];
+    assert_eq!(res_stages, expected);
+}
+
 /// Given two packages names as input, with testpkg1 being a reverse dependency for testpkg2 and
 /// testpkg3 and testpkg4 being a dependency of testpkg2. Providing "testpkg1 testpkg2" in
 /// combination with the no reverse dependenies flag should
@@ -238,15 +359,1039 @@ fn test_no_reverse_deps_flag(
     let pkgname2 = &packages[1];
 
     let res = arch_rebuild_order::run(
-        vec![pkgname1.to_string(), pkgname2.to_string()],
-        multiple_pkgnames.1,
-        multiple_pkgnames.2,
-        None,
-        true,
-        false,
+        &RebuildOrderOptions::new(vec![pkgname1.to_string(), pkgname2.to_string()])
+            .dbpath(multiple_pkgnames.1)
+            .repos(multiple_pkgnames.2)
+            .no_reverse_depends(true),
     )
-    .unwrap();
+    .unwrap()
+    .to_string();
     let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
     let expected = vec!["testpkg1", "testpkg2"];
     assert_eq!(res_pkgs, expected);
 }
+
+/// Given a package 'testpkg0' with reverse dependencies 'testpkg1' and 'testpkg1-libs' which are
+/// split packages of 'testbase', and 'testpkg2' depending on 'testpkg1-libs'. Providing
+/// 'testpkg0' with pkgbase should return 'testpkg0 testbase testpkg2'
+#[rstest]
+fn test_pkgbase(split_packages: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let packages = split_packages.0;
+    let pkgname = &packages[0];
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(split_packages.1)
+            .repos(split_packages.2)
+            .pkgbase(true),
+    )
+    .unwrap()
+    .to_string();
+    let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
+    assert_eq!(res_pkgs, vec!["testpkg0", "testbase", "testpkg2"]);
+}
+
+/// Given split packages 'testpkg1' and 'testpkg2' of 'testbase' with a make dependency on
+/// 'testpkg2' and 'testpkg2' depending on 'testpkg1'. Split packages never depend on each other
+/// so there should be no cycle and the rebuild order should be 'testpkg1 testpkg2'
+#[rstest]
+fn test_split_package_make_depends(
+    split_package_make_depends: (Vec<Package>, Option<String>, Vec<String>, TempDir),
+) {
+    let packages = split_package_make_depends.0;
+    let pkgname = &packages[0];
+
+    let graph = arch_rebuild_order::rebuild_graph(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(split_package_make_depends.1.clone())
+            .repos(split_package_make_depends.2.clone()),
+    )
+    .unwrap()
+    .graph;
+    assert_eq!(graph.node_count(), 2);
+    assert_eq!(graph.edge_count(), 0);
+    assert!(find_cycles(&graph).is_empty());
+
+    let options = RebuildOrderOptions::new(vec![pkgname.to_string()])
+        .dbpath(split_package_make_depends.1)
+        .repos(split_package_make_depends.2);
+    let res = arch_rebuild_order::run(&options).unwrap().to_string();
+    let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
+    assert_eq!(packages, res_pkgs);
+
+    let plan = arch_rebuild_order::run(&options.why(Some("testpkg2".to_string()))).unwrap();
+    assert_eq!(
+        plan.chains[0].to_string(),
+        "testpkg1 -> testpkg2 (split package)"
+    );
+}
+
+/// Given a package 'testpkg1' providing 'libtest.so' with 'testpkg2' depending on 'libtest.so' and
+/// 'testpkg3' depending on 'testpkg1'. Providing 'testpkg1' with soname should return
+/// 'testpkg1 testpkg2' as only 'testpkg2' links against the soname.
+#[rstest]
+fn test_soname(soname_depends: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let packages = soname_depends.0;
+    let pkgname = &packages[0];
+
+    let graph = arch_rebuild_order::rebuild_graph(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(soname_depends.1.clone())
+            .repos(soname_depends.2.clone())
+            .soname(true),
+    )
+    .unwrap()
+    .graph;
+    let edges: Vec<_> = graph
+        .edge_indices()
+        .map(|edge| graph[edge].provide.clone())
+        .collect();
+    assert_eq!(edges, vec![Some("libtest.so".to_string())]);
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(soname_depends.1)
+            .repos(soname_depends.2)
+            .soname(true),
+    )
+    .unwrap()
+    .to_string();
+    let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
+    assert_eq!(res_pkgs, vec!["testpkg1", "testpkg2"]);
+}
+
+/// Given a package 'testpkg1' with a reverse dependency 'testpkg2' providing 'virtual', which
+/// 'testpkg3' depends on and 'testpkg4' also provides. Providing 'testpkg1' should return
+/// 'testpkg1 testpkg2 testpkg3' and report 'virtual' as provided by multiple packages.
+#[rstest]
+fn test_provides_depth(provides_depth: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let packages = provides_depth.0;
+    let pkgname = &packages[0];
+
+    let rebuild = arch_rebuild_order::rebuild_graph(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(provides_depth.1.clone())
+            .repos(provides_depth.2.clone()),
+    )
+    .unwrap();
+    assert_eq!(rebuild.ambiguous_provides.len(), 1);
+    assert_eq!(rebuild.ambiguous_provides[0].name, "virtual");
+    assert_eq!(
+        rebuild.ambiguous_provides[0].providers,
+        vec!["testpkg2", "testpkg4"]
+    );
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(provides_depth.1)
+            .repos(provides_depth.2),
+    )
+    .unwrap()
+    .to_string();
+    let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
+    assert_eq!(res_pkgs, vec!["testpkg1", "testpkg2", "testpkg3"]);
+}
+
+/// Given a package 'testpkg1' of version '1-1' providing 'virtual=1', with reverse dependencies
+/// 'testpkg2' depending on 'testpkg1<2', 'testpkg3' on 'testpkg1>=2' and 'testpkg4' on
+/// 'virtual>=2'. Providing 'testpkg1' should only return 'testpkg1 testpkg2', bumping it to '2-1'
+/// should add 'testpkg3' and report the constraint of 'testpkg2' as broken.
+#[rstest]
+fn test_versioned_depends(versioned_depends: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let packages = versioned_depends.0;
+    let pkgname = &packages[0];
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(versioned_depends.1.clone())
+            .repos(versioned_depends.2.clone()),
+    )
+    .unwrap()
+    .to_string();
+    let res_pkgs: Vec<&str> = res.trim().split_ascii_whitespace().collect();
+    assert_eq!(res_pkgs, vec!["testpkg1", "testpkg2"]);
+
+    let rebuild = arch_rebuild_order::rebuild_graph(
+        &RebuildOrderOptions::new(vec![pkgname.to_string()])
+            .dbpath(versioned_depends.1)
+            .repos(versioned_depends.2)
+            .bumps(vec![(pkgname.to_string(), "2-1".to_string())]),
+    )
+    .unwrap();
+    let mut nodes: Vec<&str> = rebuild.graph.node_weights().map(|x| x.as_str()).collect();
+    nodes.sort();
+    assert_eq!(nodes, vec!["testpkg1", "testpkg2", "testpkg3"]);
+
+    assert_eq!(rebuild.broken_constraints.len(), 1);
+    assert_eq!(
+        rebuild.broken_constraints[0].to_string(),
+        "bumping testpkg1 to 2-1 breaks testpkg2 depends testpkg1<2"
+    );
+}
+
+/// Given a package 'testpkg1' with reverse dependencies 'testpkg2' and 'testpkg3', where
+/// 'testpkg3' also depends on 'testpkg2'. The shortest chain to 'testpkg3' is its direct
+/// dependency on 'testpkg1', all chains include the chain through 'testpkg2'.
+#[rstest]
+fn test_why(multiple_deps: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let pkgnames = vec!["testpkg1".to_string()];
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(pkgnames.clone())
+            .dbpath(multiple_deps.1.clone())
+            .repos(multiple_deps.2.clone())
+            .why(Some("testpkg3".to_string())),
+    )
+    .unwrap();
+    let chains: Vec<String> = res.chains.iter().map(|x| x.to_string()).collect();
+    assert_eq!(chains, vec!["testpkg1 -> testpkg3 (depends)"]);
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(pkgnames.clone())
+            .dbpath(multiple_deps.1.clone())
+            .repos(multiple_deps.2.clone())
+            .why(Some("testpkg3".to_string()))
+            .all_chains(true),
+    )
+    .unwrap();
+    let chains: Vec<String> = res.chains.iter().map(|x| x.to_string()).collect();
+    assert_eq!(
+        chains,
+        vec![
+            "testpkg1 -> testpkg3 (depends)",
+            "testpkg1 -> testpkg2 (depends) -> testpkg3 (depends)"
+        ]
+    );
+
+    let res = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(pkgnames)
+            .dbpath(multiple_deps.1)
+            .repos(multiple_deps.2)
+            .why(Some("testpkg4".to_string())),
+    );
+    assert!(res.is_err());
+}
+
+/// Given a package 'testpkg1' with a reverse dependency 'testpkg2' providing 'virtual', which
+/// 'testpkg3' depends on. The chain to 'testpkg3' should label its dependency with the provide.
+#[rstest]
+fn test_why_provides(provides_depth: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let pkgnames = vec!["testpkg1".to_string()];
+
+    let rebuild = arch_rebuild_order::rebuild_graph(
+        &RebuildOrderOptions::new(pkgnames.clone())
+            .dbpath(provides_depth.1)
+            .repos(provides_depth.2),
+    )
+    .unwrap();
+    let (graph, packages) = (&rebuild.graph, &rebuild.packages);
+    let chain = shortest_chain(graph, &pkgnames, packages, "testpkg3").unwrap();
+    assert_eq!(chain.root, "testpkg1");
+    assert_eq!(chain.hops.len(), 2);
+    assert_eq!(
+        chain.hops[1].1,
+        Some(Dependency {
+            kind: DependKind::Depends,
+            provide: Some("virtual".to_string()),
+        })
+    );
+    assert_eq!(
+        chain.to_string(),
+        "testpkg1 -> testpkg2 (depends) -> testpkg3 (depends via virtual)"
+    );
+
+    assert_eq!(
+        all_chains(graph, &pkgnames, packages, "testpkg3"),
+        vec![chain]
+    );
+    assert!(shortest_chain(graph, &pkgnames, packages, "testpkg4").is_none());
+}
+
+/// Given a package 'testpkg1' with reverse dependencies 'testpkg2' and 'testpkg3', where
+/// 'testpkg3' also depends on 'testpkg2'. Every entry of the plan should describe the package,
+/// its depth, stage and why it is rebuilt.
+#[rstest]
+fn test_plan(multiple_deps: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+            .dbpath(multiple_deps.1)
+            .repos(multiple_deps.2),
+    )
+    .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+
+    let entry = &plan.entries[0];
+    assert_eq!(entry.pkgname, "testpkg1");
+    assert_eq!(entry.pkgbase, "testpkg1");
+    assert_eq!(entry.repo, "test");
+    assert_eq!(entry.version, "1.0-1");
+    assert_eq!(entry.depth, 0);
+    assert_eq!(entry.stage, 0);
+    assert_eq!(entry.reason, Reason::Provided);
+
+    let entry = &plan.entries[2];
+    assert_eq!(entry.pkgname, "testpkg3");
+    assert_eq!(entry.version, "1-1");
+    assert_eq!(entry.depth, 1);
+    assert_eq!(entry.stage, 2);
+    assert_eq!(
+        entry.reason.to_string(),
+        "reverse dependency of testpkg1 (depends)"
+    );
+}
+
+/// Given split packages 'testpkg1' and 'testpkg2' of 'testbase', providing 'testpkg1' should
+/// include 'testpkg2' as split package of 'testpkg1'.
+#[rstest]
+fn test_plan_split_package(
+    split_package_make_depends: (Vec<Package>, Option<String>, Vec<String>, TempDir),
+) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+            .dbpath(split_package_make_depends.1)
+            .repos(split_package_make_depends.2),
+    )
+    .unwrap();
+
+    let entry = &plan.entries[1];
+    assert_eq!(entry.pkgname, "testpkg2");
+    assert_eq!(entry.pkgbase, "testbase");
+    assert_eq!(entry.depth, 0);
+    assert_eq!(
+        entry.reason,
+        Reason::SplitPackage {
+            pkgname: "testpkg1".to_string()
+        }
+    );
+}
+
+/// Given a package 'testpkg1' with a reverse dependency 'testpkg2' providing 'virtual', which
+/// 'testpkg3' depends on. The JSON output should contain the packages, their reasons and edges.
+#[rstest]
+fn test_json(provides_depth: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+            .dbpath(provides_depth.1)
+            .repos(provides_depth.2),
+    )
+    .unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&arch_rebuild_order::json::to_json(&plan).unwrap()).unwrap();
+
+    assert_eq!(json["schema_version"], 1);
+
+    let packages = json["packages"].as_array().unwrap();
+    let pkgnames: Vec<&str> = packages
+        .iter()
+        .map(|x| x["pkgname"].as_str().unwrap())
+        .collect();
+    assert_eq!(pkgnames, vec!["testpkg1", "testpkg2", "testpkg3"]);
+    assert_eq!(packages[0]["repo"], "test");
+    assert_eq!(packages[0]["stage"], 1);
+    assert_eq!(packages[0]["reason"]["type"], "provided");
+    assert_eq!(packages[2]["depth"], 2);
+    assert_eq!(packages[2]["stage"], 3);
+    assert_eq!(packages[2]["reason"]["type"], "reverse_dependency");
+    assert_eq!(packages[2]["reason"]["pkgname"], "testpkg2");
+    assert_eq!(packages[2]["reason"]["provide"], "virtual");
+
+    let edges = json["edges"].as_array().unwrap();
+    assert_eq!(edges.len(), 2);
+    assert_eq!(edges[1]["from"], "testpkg2");
+    assert_eq!(edges[1]["to"], "testpkg3");
+    assert_eq!(edges[1]["kind"], "depends");
+    assert_eq!(edges[1]["provide"], "virtual");
+}
+
+/// Given 'testpkg0' with reverse dependencies 'testpkg1' and 'testpkg1-libs' of pkgbase
+/// 'testbase', which 'testpkg2' depends on. Asking why a split package is rebuilt with merged
+/// split packages should give the chain to its pkgbase.
+#[rstest]
+fn test_why_pkgbase(split_packages: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let options = RebuildOrderOptions::new(vec!["testpkg0".to_string()])
+        .dbpath(split_packages.1)
+        .repos(split_packages.2)
+        .pkgbase(true);
+
+    for target in ["testpkg1-libs", "testbase"] {
+        let plan = arch_rebuild_order::run(&options.clone().why(Some(target.to_string()))).unwrap();
+        assert_eq!(plan.chains.len(), 1);
+        assert_eq!(plan.chains[0].to_string(), "testpkg0 -> testbase (depends)");
+    }
+}
+
+/// Given 'testpkg1' with a reverse make dependency 'testpkg2', which 'testpkg3' make depends on.
+/// Providing 'testpkg1' and 'testpkg3' without reverse dependencies, the stage left empty by
+/// 'testpkg2' should be skipped in the entries and in the JSON output alike.
+#[rstest]
+fn test_json_stages(dependency_depth: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string(), "testpkg3".to_string()])
+            .dbpath(dependency_depth.1)
+            .repos(dependency_depth.2)
+            .no_reverse_depends(true),
+    )
+    .unwrap();
+    let stages: Vec<usize> = plan.entries.iter().map(|x| x.stage).collect();
+    assert_eq!(stages, vec![0, 1]);
+    assert_eq!(plan.format_stages(), "stage 1: testpkg1\nstage 2: testpkg3");
+
+    let json: serde_json::Value = serde_json::from_str(&json::to_json(&plan).unwrap()).unwrap();
+    assert_eq!(json["packages"][0]["stage"], 1);
+    assert_eq!(json["packages"][1]["stage"], 2);
+}
+
+/// Given a package 'testpkg1' with a dependency on 'testpkg2' and 'testpkg2' having a make
+/// dependency on 'testpkg1'. The JSON output should contain the cycle with its dependencies and
+/// the make dependency dropped to break it.
+#[rstest]
+fn test_json_cycles(make_depends_cycle: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+            .dbpath(make_depends_cycle.1)
+            .repos(make_depends_cycle.2),
+    )
+    .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json::to_json(&plan).unwrap()).unwrap();
+
+    let cycles = json["cycles"].as_array().unwrap();
+    assert_eq!(cycles.len(), 1);
+    assert_eq!(
+        cycles[0]["packages"],
+        serde_json::json!(["testpkg1", "testpkg2"])
+    );
+    assert_eq!(
+        cycles[0]["edges"],
+        serde_json::json!([
+            {"from": "testpkg1", "to": "testpkg2", "kind": "makedepends"},
+            {"from": "testpkg2", "to": "testpkg1", "kind": "depends"},
+        ])
+    );
+    assert_eq!(
+        json["dropped"],
+        serde_json::json!([{"from": "testpkg1", "to": "testpkg2", "kind": "makedepends"}])
+    );
+}
+
+/// Given 'testpkg1' and 'testpkg2' providing 'virtual', 'testpkg3' depending on 'testpkg1<2' and
+/// 'testpkg4' depending on 'virtual'. Bumping 'testpkg1' to 2-1, the JSON output should contain
+/// the ambiguous provide followed and the broken version constraint.
+#[test]
+fn test_json_warnings() {
+    let mut testpkg1 = SourcePackage::new("testpkg1", "1-1");
+    testpkg1.provides = vec![Depend::parse("virtual")];
+    let mut testpkg2 = SourcePackage::new("testpkg2", "1-1");
+    testpkg2.provides = vec![Depend::parse("virtual")];
+    let mut testpkg3 = SourcePackage::new("testpkg3", "1-1");
+    testpkg3.depends = vec![Depend::parse("testpkg1<2")];
+    let mut testpkg4 = SourcePackage::new("testpkg4", "1-1");
+    testpkg4.depends = vec![Depend::parse("virtual")];
+
+    let index = DependencyIndex::from_source(&MemorySource::new(vec![
+        testpkg1, testpkg2, testpkg3, testpkg4,
+    ]));
+    let plan = index
+        .run(
+            &RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+                .bumps(vec![("testpkg1".to_string(), "2-1".to_string())]),
+        )
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json::to_json(&plan).unwrap()).unwrap();
+
+    assert_eq!(
+        json["ambiguous_provides"],
+        serde_json::json!([{"name": "virtual", "providers": ["testpkg1", "testpkg2"]}])
+    );
+    assert_eq!(
+        json["broken_constraints"],
+        serde_json::json!([{
+            "pkgname": "testpkg1",
+            "version": "2-1",
+            "rev_dep": "testpkg3",
+            "kind": "depends",
+            "constraint": "<2",
+        }])
+    );
+}
+
+/// Given a package 'testpkg1' with reverse dependencies 'testpkg2' and 'testpkg3', where
+/// 'testpkg3' also depends on 'testpkg2'. An index should answer multiple queries and be rebuilt
+/// once the syncdb changed.
+#[rstest]
+fn test_dependency_index(multiple_deps: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let dbpath = multiple_deps.1.unwrap();
+    let mut index = DependencyIndex::new(Some(&dbpath), &multiple_deps.2).unwrap();
+
+    let plan = index
+        .run(&RebuildOrderOptions::new(vec!["testpkg1".to_string()]))
+        .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+    let plan = index
+        .run(&RebuildOrderOptions::new(vec!["testpkg2".to_string()]))
+        .unwrap();
+    assert_eq!(plan.to_string(), "testpkg2 testpkg3");
+
+    let rev_deps: Vec<String> = index
+        .reverse_depends("testpkg1", false)
+        .into_iter()
+        .map(|(rev_dep, _)| rev_dep)
+        .collect();
+    assert_eq!(rev_deps, vec!["testpkg2", "testpkg3"]);
+    assert_eq!(index.package("testpkg3").unwrap().version, "1-1");
+
+    assert!(!index.is_stale());
+    File::options()
+        .write(true)
+        .open(Path::new(&dbpath).join("sync").join("test.db"))
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    assert!(index.is_stale());
+    assert!(index.refresh_if_stale().unwrap());
+    assert!(!index.is_stale());
+}
+
+/// Given a package 'testpkg1' with reverse dependencies 'testpkg2' and 'testpkg3'. The index
+/// should be written to the cache, loaded from it while the syncdb is unchanged and written again
+/// once it changed, removing the cache file of an older cache version.
+#[rstest]
+fn test_cache(multiple_deps: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let dbpath = multiple_deps.1.unwrap();
+    let repos = multiple_deps.2;
+    let cache_dir = multiple_deps.3.path().join("cache");
+
+    let index = cache::load_or_build(
+        &cache_dir,
+        None,
+        Some(&dbpath),
+        &repos,
+        DuplicatePolicy::RepoOrder,
+    )
+    .unwrap();
+    let files: Vec<_> = fs::read_dir(&cache_dir).unwrap().collect();
+    assert_eq!(files.len(), 1);
+    let cache_file = files[0].as_ref().unwrap().path();
+    let cached = fs::read_to_string(&cache_file).unwrap();
+
+    let plan = cache::load_or_build(
+        &cache_dir,
+        None,
+        Some(&dbpath),
+        &repos,
+        DuplicatePolicy::RepoOrder,
+    )
+    .unwrap()
+    .run(&RebuildOrderOptions::new(vec!["testpkg1".to_string()]))
+    .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+    assert_eq!(fs::read_to_string(&cache_file).unwrap(), cached);
+    assert!(!index.is_stale());
+
+    File::options()
+        .write(true)
+        .open(Path::new(&dbpath).join("sync").join("test.db"))
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    assert!(index.is_stale());
+    let old_cache_file = cache_dir.join("index-0123456789abcdef.json");
+    fs::write(&old_cache_file, "{}").unwrap();
+    cache::load_or_build(
+        &cache_dir,
+        None,
+        Some(&dbpath),
+        &repos,
+        DuplicatePolicy::RepoOrder,
+    )
+    .unwrap();
+    assert_ne!(fs::read_to_string(&cache_file).unwrap(), cached);
+    assert!(!old_cache_file.exists());
+
+    cache::clear(&cache_dir).unwrap();
+    assert!(!cache_dir.exists());
+}
+
+/// Given packages held in memory, 'testpkg1' with a reverse dependency 'testpkg2' through the
+/// virtual package 'virtual', and 'testpkg3' with a versioned build dependency on 'testpkg2'. The
+/// index should be built without a pacman database and give the same rebuild order.
+#[test]
+fn test_memory_source() {
+    let mut testpkg1 = SourcePackage::new("testpkg1", "1-1");
+    testpkg1.provides = vec![Depend::parse("virtual=1")];
+    let mut testpkg2 = SourcePackage::new("testpkg2", "1-1");
+    testpkg2.depends = vec![Depend::parse("virtual")];
+    let mut testpkg3 = SourcePackage::new("testpkg3", "1-1");
+    testpkg3.makedepends = vec![Depend::parse("testpkg2>=1")];
+
+    let index =
+        DependencyIndex::from_source(&MemorySource::new(vec![testpkg1, testpkg2, testpkg3]));
+    let plan = index
+        .run(&RebuildOrderOptions::new(vec!["testpkg1".to_string()]))
+        .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+    assert!(!index.is_stale());
+
+    let depend = Depend::parse("testpkg2>=1");
+    assert_eq!(depend.name, "testpkg2");
+    assert_eq!(depend.to_string(), "testpkg2>=1");
+}
+
+/// Given 'testpkg1' in 'extra' with reverse dependencies 'testpkg3' in 'extra' and 'testpkg2' in
+/// 'core', read in that order. The rebuild order should prioritize the repositories in the order
+/// of the index, not of the `repos` option.
+#[test]
+fn test_index_repo_priority() {
+    let mut packages = Vec::new();
+    for (name, repo) in [
+        ("testpkg1", "extra"),
+        ("testpkg3", "extra"),
+        ("testpkg2", "core"),
+    ] {
+        let mut pkg = SourcePackage::new(name, "1-1");
+        pkg.repo = repo.to_string();
+        if name != "testpkg1" {
+            pkg.depends = vec![Depend::parse("testpkg1")];
+        }
+        packages.push(pkg);
+    }
+
+    let index = DependencyIndex::from_source(&MemorySource::new(packages));
+    assert_eq!(index.repos(), ["extra", "core"]);
+    let plan = index
+        .run(&RebuildOrderOptions::new(vec!["testpkg1".to_string()]))
+        .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg3 testpkg2");
+}
+
+/// Given 'llvm-libs' depending on 'libffi', its split package 'llvm' only depending on
+/// 'llvm-libs' and 'foo' depending on 'llvm'. 'llvm' should be built with 'llvm-libs' after
+/// 'libffi', not in the first stage.
+#[test]
+fn test_split_package_stage() {
+    let libffi = SourcePackage::new("libffi", "1-1");
+    let mut llvm_libs = SourcePackage::new("llvm-libs", "1-1");
+    llvm_libs.base = Some("llvm".to_string());
+    llvm_libs.depends = vec![Depend::parse("libffi")];
+    let mut llvm = SourcePackage::new("llvm", "1-1");
+    llvm.depends = vec![Depend::parse("llvm-libs")];
+    let mut foo = SourcePackage::new("foo", "1-1");
+    foo.depends = vec![Depend::parse("llvm")];
+
+    let index =
+        DependencyIndex::from_source(&MemorySource::new(vec![libffi, llvm_libs, llvm, foo]));
+    let plan = index
+        .run(&RebuildOrderOptions::new(vec!["libffi".to_string()]))
+        .unwrap();
+    assert_eq!(
+        plan.format_stages(),
+        "stage 1: libffi\nstage 2: llvm llvm-libs\nstage 3: foo"
+    );
+    assert_eq!(plan.graph.edge_count(), 2);
+
+    let options = RebuildOrderOptions::new(vec!["libffi".to_string()]).why(Some("foo".to_string()));
+    let plan = index.run(&options.all_chains(true)).unwrap();
+    let chains: Vec<String> = plan.chains.iter().map(|x| x.to_string()).collect();
+    assert_eq!(
+        chains,
+        vec!["libffi -> llvm-libs (depends) -> llvm (split package) -> foo (depends)"]
+    );
+}
+
+/// Given 'app' depending on 'libfoo' provided by 'libfoo-impl' and build depending on 'tool',
+/// both depending on 'glibc', which 'other' depends on as well. Ordering the dependencies of
+/// 'app' should build every dependency before its consumers and leave out 'other', options only
+/// applying to reverse dependencies should be rejected.
+#[test]
+fn test_depends() {
+    let glibc = SourcePackage::new("glibc", "2-1");
+    let mut libfoo = SourcePackage::new("libfoo-impl", "1-1");
+    libfoo.provides = vec![Depend::parse("libfoo=1")];
+    libfoo.depends = vec![Depend::parse("glibc")];
+    let mut tool = SourcePackage::new("tool", "1-1");
+    tool.depends = vec![Depend::parse("glibc")];
+    let mut app = SourcePackage::new("app", "1-1");
+    app.depends = vec![Depend::parse("libfoo>=1")];
+    app.makedepends = vec![Depend::parse("tool")];
+    let mut other = SourcePackage::new("other", "1-1");
+    other.depends = vec![Depend::parse("glibc")];
+
+    let index =
+        DependencyIndex::from_source(&MemorySource::new(vec![glibc, libfoo, tool, app, other]));
+    let options = RebuildOrderOptions::new(vec!["app".to_string()]).depends(true);
+    let plan = index.run(&options).unwrap();
+    assert_eq!(plan.to_string(), "glibc libfoo-impl tool app");
+    assert_eq!(
+        plan.format_stages(),
+        "stage 1: glibc\nstage 2: libfoo-impl tool\nstage 3: app"
+    );
+    assert_eq!(plan.entries[3].reason, Reason::Provided);
+    assert_eq!(
+        plan.entries[1].reason,
+        Reason::Dependency {
+            pkgname: "app".to_string(),
+            dependency: Dependency {
+                kind: DependKind::Depends,
+                provide: Some("libfoo".to_string()),
+            },
+        }
+    );
+    assert_eq!(plan.entries[0].depth, 2);
+
+    for conflicting in [
+        options.clone().soname(true),
+        options.clone().no_reverse_depends(true),
+        options
+            .clone()
+            .bumps(vec![("glibc".to_string(), "3-1".to_string())]),
+        options.clone().all(true),
+    ] {
+        assert!(index.run(&conflicting).is_err());
+    }
+
+    let plan = index.run(&options.why(Some("glibc".to_string()))).unwrap();
+    assert_eq!(
+        plan.chains[0].to_string(),
+        "app -> libfoo-impl (depends via libfoo) -> glibc (depends)"
+    );
+}
+
+/// Given 'app' only depending on its split package 'app-libs', which depends on 'zlib'. Ordering
+/// the dependencies of 'app' should build it with 'app-libs' after 'zlib'.
+#[test]
+fn test_depends_split_package() {
+    let zlib = SourcePackage::new("zlib", "1-1");
+    let mut app_libs = SourcePackage::new("app-libs", "1-1");
+    app_libs.base = Some("app".to_string());
+    app_libs.depends = vec![Depend::parse("zlib")];
+    let mut app = SourcePackage::new("app", "1-1");
+    app.depends = vec![Depend::parse("app-libs")];
+
+    let index = DependencyIndex::from_source(&MemorySource::new(vec![zlib, app_libs, app]));
+    let plan = index
+        .run(&RebuildOrderOptions::new(vec!["app".to_string()]).depends(true))
+        .unwrap();
+    assert_eq!(plan.format_stages(), "stage 1: zlib\nstage 2: app app-libs");
+    assert_eq!(plan.to_string(), "zlib app app-libs");
+}
+
+/// Given 'testpkg1' and 'testpkg2' depending on each other, 'testpkg3' depending on 'testpkg1',
+/// 'testpkg4' build depending on 'testpkg3' and 'testpkg5' without dependencies. Ordering all
+/// packages should include every package without providing any, with the cycle reported and
+/// broken by a bootstrap build.
+#[test]
+fn test_all() {
+    let mut testpkg1 = SourcePackage::new("testpkg1", "1-1");
+    testpkg1.depends = vec![Depend::parse("testpkg2")];
+    let mut testpkg2 = SourcePackage::new("testpkg2", "1-1");
+    testpkg2.depends = vec![Depend::parse("testpkg1")];
+    let mut testpkg3 = SourcePackage::new("testpkg3", "1-1");
+    testpkg3.depends = vec![Depend::parse("testpkg1")];
+    let mut testpkg4 = SourcePackage::new("testpkg4", "1-1");
+    testpkg4.makedepends = vec![Depend::parse("testpkg3")];
+    let testpkg5 = SourcePackage::new("testpkg5", "1-1");
+
+    let index = DependencyIndex::from_source(&MemorySource::new(vec![
+        testpkg1, testpkg2, testpkg3, testpkg4, testpkg5,
+    ]));
+    let plan = index
+        .run(&RebuildOrderOptions::new(Vec::new()).all(true))
+        .unwrap();
+    assert_eq!(
+        plan.format_stages(),
+        "stage 1: testpkg1:bootstrap testpkg5\nstage 2: testpkg2\nstage 3: testpkg1\n\
+         stage 4: testpkg3\nstage 5: testpkg4"
+    );
+    assert_eq!(plan.entries.len(), 6);
+    assert_eq!(plan.cycles.len(), 1);
+    assert_eq!(plan.cycles[0].packages, vec!["testpkg1", "testpkg2"]);
+}
+
+/// Given repository databases in files, 'testpkg1' in an uncompressed 'core', 'testpkg2' depending
+/// on it in a gzip compressed 'extra', 'testpkg3' build depending on 'testpkg2' in a zstd
+/// compressed 'multilib' and 'testpkg4' depending on 'testpkg3' in a xz compressed 'testing'. The
+/// rebuild order should be read from the files without a pacman database.
+#[rstest]
+fn test_db_files(db_files: (Vec<(String, String)>, TempDir)) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()]).db_files(db_files.0),
+    )
+    .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3 testpkg4");
+    let repos: Vec<&str> = plan.entries.iter().map(|x| x.repo.as_str()).collect();
+    assert_eq!(repos, vec!["core", "extra", "multilib", "testing"]);
+
+    let missing = vec![("core".to_string(), "/non-existant-path".to_string())];
+    let err = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()]).db_files(missing),
+    )
+    .unwrap_err();
+    let io_error = err.chain().find_map(|e| e.downcast_ref::<io::Error>());
+    assert_eq!(io_error.map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+}
+
+/// Given a repository database of an older format with the dependencies in a separate
+/// 'depends' entry, following a 'desc' entry without trailing newline. Both entries should be
+/// read in full.
+#[test]
+fn test_db_depends_entry() {
+    let mut archive = tar::Builder::new(Vec::new());
+    for (path, content) in [
+        ("testpkg2-1-1/desc", "%NAME%\ntestpkg2\n\n%VERSION%\n1-1"),
+        ("testpkg2-1-1/depends", "%DEPENDS%\ntestpkg1\n"),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        archive
+            .append_data(&mut header, path, content.as_bytes())
+            .unwrap();
+    }
+    let db = archive.into_inner().unwrap();
+
+    let packages = repodb::read_db("core", db.as_slice()).unwrap();
+    assert_eq!(packages.len(), 1);
+    assert_eq!(packages[0].version, "1-1");
+    assert_eq!(packages[0].depends, vec![Depend::parse("testpkg1")]);
+}
+
+/// Given .SRCINFO files of 'testpkg1' split into 'testpkg1-libs' providing 'libtest.so',
+/// 'testpkg2' depending on 'libtest.so' on x86_64 and on 'testpkg1' on aarch64, 'testpkg3'
+/// depending on 'testpkg1' and build depending on 'testpkg2' except for its split package
+/// 'testpkg3-docs', and 'testpkg4' only built for aarch64. The rebuild order should follow the
+/// dependencies of the given architecture.
+#[rstest]
+fn test_srcinfo(srcinfo_tree: TempDir) {
+    let options =
+        RebuildOrderOptions::new(vec!["testpkg1".to_string(), "testpkg1-libs".to_string()])
+            .srcinfo(Some(srcinfo_tree.path().display().to_string()))
+            .srcinfo_only(true)
+            .pkgbase(true);
+
+    let plan = arch_rebuild_order::run(&options.clone()).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+    assert_eq!(plan.entries[0].version, "1:2-1");
+    assert_eq!(plan.entries[2].arch.as_deref(), Some("any"));
+
+    let plan = arch_rebuild_order::run(&options.arch("aarch64".to_string())).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3 testpkg4");
+
+    let index =
+        DependencyIndex::from_source(&SrcinfoSource::open(srcinfo_tree.path(), "x86_64").unwrap());
+    assert_eq!(
+        index.reverse_depends("testpkg1", false),
+        vec![(
+            "testpkg3".to_string(),
+            Dependency {
+                kind: DependKind::Depends,
+                provide: None
+            }
+        )]
+    );
+    assert!(index.package("testpkg4").is_none());
+}
+
+/// Given repository databases with the chain 'testpkg1' to 'testpkg4' and a .SRCINFO of
+/// 'testpkg3' build depending on 'testpkg1' instead of 'testpkg2'. The .SRCINFO should replace
+/// 'testpkg3' of the 'multilib' repository.
+#[rstest]
+fn test_srcinfo_overlay(db_files: (Vec<(String, String)>, TempDir), srcinfo_overlay: TempDir) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+            .db_files(db_files.0)
+            .srcinfo(Some(srcinfo_overlay.path().display().to_string())),
+    )
+    .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3 testpkg4");
+
+    let testpkg3 = &plan.entries[2];
+    assert_eq!(testpkg3.repo, "multilib");
+    assert_eq!(testpkg3.version, "2-1");
+    assert_eq!(testpkg3.depth, 1);
+}
+
+/// Given repository databases with the chain 'testpkg1' to 'testpkg4' and built packages of
+/// 'testpkg3' version 2-1 depending on 'testpkg1' and an older version 1.5-1 depending on
+/// 'testpkg2'. The newest built package should replace 'testpkg3' of the 'multilib' repository.
+#[rstest]
+fn test_built_packages(db_files: (Vec<(String, String)>, TempDir), built_packages: TempDir) {
+    let plan = arch_rebuild_order::run(
+        &RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+            .db_files(db_files.0)
+            .built_packages(Some(built_packages.path().display().to_string())),
+    )
+    .unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3 testpkg4");
+
+    let testpkg3 = &plan.entries[2];
+    assert_eq!(testpkg3.repo, "multilib");
+    assert_eq!(testpkg3.version, "2-1");
+    assert_eq!(
+        testpkg3.reason,
+        Reason::ReverseDependency {
+            pkgname: "testpkg1".to_string(),
+            dependency: Dependency {
+                kind: DependKind::Depends,
+                provide: None
+            }
+        }
+    );
+}
+
+/// Given a package 'testpkg1' with reverse dependencies 'testpkg2' and 'testpkg3' in the
+/// repository 'test', included from a pacman.conf setting the DBPath. The repositories, DBPath
+/// and Architecture should be read from the pacman.conf.
+#[rstest]
+fn test_pacman_conf(multiple_deps: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let dbpath = multiple_deps.1.unwrap();
+    let confdir = multiple_deps.3.path().join("conf");
+    fs::create_dir_all(confdir.join("repos.d")).unwrap();
+    fs::write(
+        confdir.join("pacman.conf"),
+        format!(
+            "[options]
+# comment
+DBPath = {dbpath}
+Architecture = aarch64 auto
+
+Include = {}/repos.d/*.conf
+
+[multilib] # comments after a section are stripped
+",
+            confdir.display()
+        ),
+    )
+    .unwrap();
+    fs::write(
+        confdir.join("repos.d").join("test.conf"),
+        "[test]\nServer = https://example.org/$repo/os/$arch\n",
+    )
+    .unwrap();
+
+    let conf = PacmanConf::parse_file(&confdir.join("pacman.conf")).unwrap();
+    assert_eq!(conf.db_path.as_deref(), Some(dbpath.as_str()));
+    assert_eq!(conf.architecture.as_deref(), Some("aarch64"));
+    assert_eq!(conf.repos, vec!["test", "multilib"]);
+
+    let options = RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+        .pacman_conf(&conf)
+        .repos(multiple_deps.2);
+    let plan = arch_rebuild_order::run(&options).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+
+    fs::write(confdir.join("root.conf"), "[options]\nRootDir = /chroot\n").unwrap();
+    let conf = PacmanConf::parse_file(&confdir.join("root.conf")).unwrap();
+    assert_eq!(conf.root_dir.as_deref(), Some("/chroot"));
+    assert_eq!(conf.db_path, None);
+    assert!(PacmanConf::parse_file(&confdir.join("missing.conf")).is_err());
+}
+
+/// Given a package 'testpkg1' with reverse dependencies 'testpkg2' and 'testpkg3' in a pacman
+/// database in 'var/lib/pacman' of a root directory. The pacman database should be found in the
+/// root directory unless given explicitly.
+#[rstest]
+fn test_root(multiple_deps: (Vec<Package>, Option<String>, Vec<String>, TempDir)) {
+    let dbpath = Path::new(multiple_deps.1.as_deref().unwrap()).to_path_buf();
+    let rootdir = TempDir::new().unwrap();
+    for dir in ["local", "sync"] {
+        let target = rootdir.path().join("var/lib/pacman").join(dir);
+        fs::create_dir_all(&target).unwrap();
+        for entry in fs::read_dir(dbpath.join(dir)).unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(entry.path(), target.join(entry.file_name())).unwrap();
+        }
+    }
+    let root = rootdir.path().display().to_string();
+
+    let options = RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+        .root(Some(root.clone()))
+        .repos(multiple_deps.2);
+    let plan = arch_rebuild_order::run(&options).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+
+    let index = DependencyIndex::with_root(
+        Some(&root),
+        None,
+        &["test".to_string()],
+        DuplicatePolicy::RepoOrder,
+    )
+    .unwrap();
+    assert!(!index.is_stale());
+
+    assert!(
+        arch_rebuild_order::run(&options.dbpath(Some(format!("{root}/non-existant-path"))))
+            .is_err()
+    );
+}
+
+/// Given a package 'testpkg1' with a reverse dependency 'testpkg2' in 'core', and 'testpkg2'
+/// without dependencies and 'testpkg3' depending on 'testpkg1' in 'core-testing'. With the
+/// testing layer 'testpkg2' of 'core-testing' should shadow 'testpkg2' of 'core'.
+#[rstest]
+fn test_testing_layer(testing_repo: (Option<String>, TempDir)) {
+    let options = RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+        .dbpath(testing_repo.0)
+        .repos(vec!["core".to_string()]);
+
+    let plan = arch_rebuild_order::run(&options.clone()).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2");
+
+    let plan = arch_rebuild_order::run(&options.layers(vec!["testing".to_string()])).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg3");
+    let repos: Vec<&str> = plan.entries.iter().map(|x| x.repo.as_str()).collect();
+    assert_eq!(repos, vec!["core", "core-testing"]);
+}
+
+/// Given a package 'testpkg1' with a reverse dependency 'testpkg2' 1-1 in 'core', and 'testpkg2'
+/// 2-1 without dependencies and 'testpkg3' depending on 'testpkg1' in 'core-testing'. The
+/// duplicate 'testpkg2' should be chosen by repository order or by the highest version and the
+/// other one reported as shadowed.
+#[rstest]
+fn test_duplicates(testing_repo: (Option<String>, TempDir)) {
+    let options = RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+        .dbpath(testing_repo.0)
+        .repos(vec!["core".to_string(), "core-testing".to_string()]);
+
+    let plan = arch_rebuild_order::run(&options.clone()).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg2 testpkg3");
+    assert_eq!(
+        plan.shadowed
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>(),
+        vec!["testpkg2 2-1 in core-testing is shadowed by 1-1 in core"]
+    );
+
+    let plan =
+        arch_rebuild_order::run(&options.duplicates(DuplicatePolicy::HighestVersion)).unwrap();
+    assert_eq!(plan.to_string(), "testpkg1 testpkg3");
+    assert!(plan.shadowed.is_empty());
+}
+
+/// Given a package 'testpkg1' with reverse dependencies 'testpkg5', 'testpkg3', 'testpkg4' and
+/// 'testpkg2' in 'core', 'testpkg0' in 'extra' and 'testpkg6' depending on 'testpkg0' and
+/// 'testpkg2'. The rebuild order should be the same on every run, ordering packages by
+/// repository priority and then by name.
+#[rstest]
+fn test_reproducible_order(repo_priority: (Option<String>, Vec<String>, TempDir)) {
+    let options = RebuildOrderOptions::new(vec!["testpkg1".to_string()])
+        .dbpath(repo_priority.0)
+        .repos(repo_priority.1);
+
+    let plan = arch_rebuild_order::run(&options).unwrap();
+    assert_eq!(
+        plan.to_string(),
+        "testpkg1 testpkg2 testpkg3 testpkg4 testpkg5 testpkg0 testpkg6"
+    );
+
+    let json = json::to_json(&plan).unwrap();
+    for _ in 0..10 {
+        let plan = arch_rebuild_order::run(&options).unwrap();
+        assert_eq!(json::to_json(&plan).unwrap(), json);
+        assert_eq!(
+            plan.format_stages(),
+            "stage 1: testpkg1\n\
+             stage 2: testpkg0 testpkg2 testpkg3 testpkg4 testpkg5\n\
+             stage 3: testpkg6"
+        );
+    }
+}